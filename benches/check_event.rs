@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{channel, sync_channel};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use baddns::dict::{Dict, StreamDict};
+use baddns::dict_set::DictSet;
+use baddns::wildcards::Wildcards;
+use baddns::gen_handler::Item;
+use baddns::mem_util::QueueMessage;
+use baddns::check_handler::{check_event, CheckOptions};
+use baddns::exclude::{Exclusions, ExcludeMode};
+use baddns::psl::SuffixList;
+use baddns::checkpoint::Checkpoint;
+
+const WHITELIST: usize = 100_000;
+const ITEMS: usize = 10_000;
+
+fn whitelist() -> Wildcards {
+    let mut w = Wildcards::new();
+    for i in 0..WHITELIST {
+        w.set_item(format!("10.{}.{}.{}", i >> 16, (i >> 8) & 0xff, i & 0xff));
+    }
+    w
+}
+
+fn items() -> Vec<Item> {
+    (0..ITEMS).map(|i| Item {
+        sub: format!("sub{}", i),
+        subdomain: format!("sub{}.example.com.", i),
+        depth: 1,
+        collect: Some(vec![format!("192.168.{}.{}", (i >> 8) & 0xff, i & 0xff)]),
+        sample: false,
+        index: None,
+    }).collect()
+}
+
+fn bench_check_event(c: &mut Criterion) {
+    let w = whitelist();
+    let depth = Dict::from_vec(vec!["api".to_string(), "dev".to_string(), "test".to_string()]);
+    let items = items();
+
+    let mut group = c.benchmark_group("check_event");
+    group.throughput(Throughput::Elements(ITEMS as u64));
+    group.sample_size(10);
+    group.bench_function("whitelist_100k", |b| {
+        b.iter_batched(|| (w.clone(), depth.clone()), |(w, depth)| {
+            let (check_send, check_recv) = channel();
+            let (gen_send, _gen_recv) = channel();
+            let (result_send, _result_recv) = sync_channel(items.len());
+            let (statistical_send, _statistical_recv) = channel();
+
+            let options = CheckOptions {
+                depth: 2,
+                depth_dict: depth,
+                dicts: DictSet::new(StreamDict::new(&[], false, false).unwrap()),
+                wildcards: w,
+                permutator: None,
+                learner: None,
+                grower: None,
+                sampler: None,
+                exclude: Exclusions::new(),
+                exclude_mode: ExcludeMode::Drop,
+                psl: SuffixList::embedded(),
+                checkpointer: None,
+                resume: Checkpoint::default(),
+                stop: Arc::new(AtomicBool::new(false)),
+            };
+            let handler = check_event(check_recv, gen_send, result_send, options, statistical_send);
+            for item in items.iter() {
+                check_send.send(QueueMessage::Job(item.clone())).unwrap();
+            }
+            check_send.send(QueueMessage::Terminate).unwrap();
+            handler.join().unwrap();
+        }, BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+fn bench_is_exist(c: &mut Criterion) {
+    let w = whitelist();
+    let probe = "192.168.1.1".to_string();
+    c.bench_function("wildcards_is_exist_100k", |b| b.iter(|| w.is_exist(&probe)));
+}
+
+criterion_group!(benches, bench_check_event, bench_is_exist);
+criterion_main!(benches);
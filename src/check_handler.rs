@@ -1,52 +1,155 @@
 use std::thread;
-use std::sync::mpsc::{Sender, Receiver};
+use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Sender, SyncSender, Receiver};
 
-use crate::mem_util::{QueueMessage, Statue};
+use crate::mem_util::{send_statue, QueueMessage, Statue};
 use crate::write_handler::ResultsSubDomain;
 use crate::dict::Dict;
+use crate::dict_set::DictSet;
 use crate::wildcards::Wildcards;
+use crate::permutation::Permutator;
+use crate::learn::Learner;
+use crate::depth_grow::DepthGrower;
+use crate::exclude::{Exclusions, ExcludeMode};
+use crate::psl::SuffixList;
+use crate::checkpoint::{Checkpoint, Checkpointer};
+use crate::sampling::{SampleResult, Sampler};
+use crate::gen_handler::{gen_item, Item};
 
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct GenItem {
     pub domain: String,
-    pub depth: usize
+    pub depth: usize,
+    pub skip: usize,
 }
 
 
+/// What the check stage decides with, built by `scanner::ScanBuilder`.
+pub struct CheckOptions {
+    pub depth: usize,
+    pub depth_dict: Dict,
+    pub dicts: DictSet,
+    pub wildcards: Wildcards,
+    pub permutator: Option<Permutator>,
+    pub learner: Option<Learner>,
+    pub grower: Option<DepthGrower>,
+    pub sampler: Option<Sampler>,
+    pub exclude: Exclusions,
+    pub exclude_mode: ExcludeMode,
+    pub psl: SuffixList,
+    pub checkpointer: Option<Checkpointer>,
+    pub resume: Checkpoint,
+    // set when the scan is stopping, an unfinished scan keeps its checkpoint
+    pub stop: Arc<AtomicBool>,
+}
+
 pub fn check_event(check_recv: Receiver<QueueMessage>,
                    gen_send: Sender<QueueMessage>,
-                   result_send: Sender<ResultsSubDomain>,
-                   depth: usize, depth_dict: Dict,
-                   w: Wildcards,
+                   result_send: SyncSender<ResultsSubDomain>,
+                   options: CheckOptions,
                    statistical_send: Sender<Statue> ) -> thread::JoinHandle<()> {
+    // held until the resumed expansions below are registered
+    send_statue(&statistical_send, Statue::Expand(String::new(), 0), &options.stop);
+
     thread::spawn(move || {
+        let mut o = options;
+        let resume = std::mem::take(&mut o.resume);
+        if let Some(l) = o.learner.as_mut() {
+            l.restore(&resume.learned);
+        }
+        // domains whose subtree has already been handed to the gen thread
+        let mut expanded: HashSet<String> = resume.expanded.iter().cloned().collect();
+        // a name can be found twice, e.g. by the dictionary and by a permutation
+        let mut reported: HashSet<String> = resume.results.iter().map(|r| r.subdomain.to_owned()).collect();
         let result_send = result_send.clone();
-        let w = w.clone();
-        let depth_dict = depth_dict.clone();
         let gen_send = gen_send.clone();
         let statistical_send = statistical_send.clone();
 
+        // continue the expansions of a resumed scan where they stopped
+        for e in resume.pending {
+            let items = GenItem {
+                domain: e.domain.to_owned(),
+                depth: e.depth,
+                skip: e.done,
+            };
+            if let (true, Some(s)) = (e.sample, o.sampler.as_mut()) {
+                // samples are small, they are simply taken again
+                let parent = Item {
+                    sub: e.sub.to_owned(),
+                    subdomain: e.domain.to_owned(),
+                    depth: e.depth - 1,
+                    collect: None,
+                    sample: false,
+                    index: None,
+                };
+                let size = s.start(&parent, o.dicts.select(e.domain.as_str(), e.depth).len());
+                if size > 0 {
+                    send_sample(&mut o, items, e.sub.as_str(), size, &gen_send, &statistical_send);
+                }
+                continue
+            }
+
+            extend_gen(&o.dicts, &items, &o.learner, &statistical_send, &o.stop);
+            track(&mut o.checkpointer, &items, e.sub.as_str(), false);
+            match gen_send.send(QueueMessage::Gen(items)) {
+                Ok(_) => {}
+                Err(_) => {
+                    error!("[check_handler] send gen_send");
+                }
+            }
+        }
+        // and the permutations and learned words that were still queued
+        if !resume.candidates.is_empty() {
+            let items: Vec<Item> = resume.candidates.iter().map(|c| c.item()).collect();
+            // they may belong to several targets, so to none
+            send_statue(&statistical_send, Statue::Expand(String::new(), items.len()), &o.stop);
+            match gen_send.send(QueueMessage::Candidates(String::new(), items)) {
+                Ok(_) => {}
+                Err(_) => {
+                    error!("[check_handler] send gen_send");
+                }
+            }
+        }
+        send_statue(&statistical_send, Statue::Expanded(String::new(), 0), &o.stop);
+
         for q in check_recv {
             match q {
                 QueueMessage::Job(item) => {
-                    // set check statistical
-                    match statistical_send.send(Statue::Check) {
-                        Ok(_) => {},
-                        Err(_) => {
-                            error!("[check_handler] send Statue::Check");
+                    let name = item.subdomain.to_owned();
+
+                    // saved before this item so the checkpoint never holds it half processed
+                    if let Some(c) = o.checkpointer.as_mut() {
+                        c.save_due(&expanded);
+                        c.complete(&item);
+                    }
+
+                    if item.sample {
+                        let hit = match item.collect.as_ref() {
+                            Some(c) if !c.is_empty() => check_wildcards(&o.wildcards, c) && !o.exclude.is_excluded_collect(c),
+                            _ => false,
+                        };
+                        let result = o.sampler.as_mut().and_then(|s| s.record(&item, hit));
+                        if let Some(r) = result {
+                            sampled(&mut o, r, &gen_send, &statistical_send);
                         }
-                    };
-                    
+                    }
+
                     // Add: 
                     // Subdomain generation is required regardless of
                     // whether the primary domain has a result or the result is whitelist
 
-                    if item.depth.eq(&0) {
+                    if item.depth.eq(&0) && !o.exclude.covers(&item.subdomain) {
                         let items = GenItem {
                             depth: item.depth +1,
                             domain: item.subdomain.to_owned(),
+                            skip: 0,
                         };
+                        extend_gen(&o.dicts, &items, &o.learner, &statistical_send, &o.stop);
+                        track(&mut o.checkpointer, &items, "", false);
+                        expanded.insert(item.subdomain.to_owned());
 
                         // send gen_send
                         match gen_send.send(QueueMessage::Gen(items)) {
@@ -58,39 +161,78 @@ pub fn check_event(check_recv: Receiver<QueueMessage>,
                     };
 
                     // check collect is None or vec list
-                    if check_collect(&item.collect) {
-                        let collect = item.collect.clone().unwrap();
+                    if let Some(collect) = item.collect.clone().filter(|c| !c.is_empty()) {
+
+                        let excluded = o.exclude.is_excluded_collect(&collect);
 
                         // check item depth
-                        if item.depth.eq(&0) {
-                            let t  = gen_result(&item.subdomain, &collect);
-                            
-                            match result_send.send(t) {
-                                Ok(_) => {statistical_send.send(Statue::Writes).unwrap();}
-                                Err(_) => {
-                                    error!("[check_handler] send Statue::Writes");
-                                }
+                        if excluded && o.exclude_mode == ExcludeMode::Drop {
+                            debug!("[check_handler] drop excluded {} {:?}", item.subdomain, collect);
+                        } else if item.depth.eq(&0) {
+                            if !excluded {
+                                learn_words(&mut o.learner, &mut o.checkpointer, &item, &collect, &gen_send, &statistical_send, &o.stop);
                             }
+                            let t  = gen_result(&item.subdomain, &collect, excluded);
+                            send_result(&result_send, &mut reported, &mut o.checkpointer, t);
                         } else {
-                            if check_wildcards(&w, &collect) {
-                                let t = gen_result(&item.subdomain, &collect);
-                                match result_send.send(t) {
-                                    Ok(_) => {statistical_send.send(Statue::Writes).unwrap();}
-                                    Err(_) => {
-                                        error!("[check_handler] send Statue::Writes");
+                            if check_wildcards(&o.wildcards, &collect) && excluded {
+                                // flagged results are reported but never expanded
+                                let t = gen_result(&item.subdomain, &collect, true);
+                                send_result(&result_send, &mut reported, &mut o.checkpointer, t);
+                            } else if check_wildcards(&o.wildcards, &collect) {
+                                learn_words(&mut o.learner, &mut o.checkpointer, &item, &collect, &gen_send, &statistical_send, &o.stop);
+                                grow_depth(&mut o, &mut expanded, &item, &collect, &gen_send, &statistical_send);
+
+                                // feed variations of the confirmed name back into the queue
+                                if let Some(p) = o.permutator.as_mut() {
+                                    let items = p.permute(&item, o.depth, &expanded);
+                                    if !items.is_empty() {
+                                        if let Some(c) = o.checkpointer.as_mut() {
+                                            items.iter().for_each(|i| c.queued(i));
+                                        }
+                                        send_statue(&statistical_send, Statue::Expand(item.subdomain.to_owned(), items.len()), &o.stop);
+                                        // released under the name it was registered with, the
+                                        // variants are siblings and may belong to another target
+                                        match gen_send.send(QueueMessage::Candidates(item.subdomain.to_owned(), items)) {
+                                            Ok(_) => {}
+                                            Err(_) => {
+                                                error!("[check_handler] send gen_send");
+                                            }
+                                        }
                                     }
                                 }
 
-                                if check_depth(item.depth, depth) {
-                                    if check_depth_dict(&item.sub, &depth_dict) {
+                                let t = gen_result(&item.subdomain, &collect, false);
+                                send_result(&result_send, &mut reported, &mut o.checkpointer, t);
+
+                                // names below a public suffix belong to other registrants
+                                if check_depth(item.depth, o.depth) && !expanded.contains(&item.subdomain)
+                                    && !o.exclude.covers(&item.subdomain) && !o.psl.is_public_suffix(&item.subdomain) {
+                                    // sample policy: query the top candidates before the full subtree
+                                    if let Some(s) = o.sampler.as_mut() {
                                         let items = GenItem {
                                             domain: item.subdomain.to_owned(),
                                             depth: item.depth + 1,
+                                            skip: 0,
                                         };
+                                        let available = o.dicts.select(items.domain.as_str(), items.depth).len();
+                                        let size = s.start(&item, available);
+                                        if size > 0 {
+                                            expanded.insert(item.subdomain.to_owned());
+                                            send_sample(&mut o, items, item.sub.as_str(), size, &gen_send, &statistical_send);
+                                        }
+                                    } else if check_depth_dict(&item.sub, &o.depth_dict) {
+                                        let items = GenItem {
+                                            domain: item.subdomain.to_owned(),
+                                            depth: item.depth + 1,
+                                            skip: 0,
+                                        };
+                                        extend_gen(&o.dicts, &items, &o.learner, &statistical_send, &o.stop);
+                                        track(&mut o.checkpointer, &items, item.sub.as_str(), false);
+                                        expanded.insert(item.subdomain.to_owned());
 
                                         match gen_send.send(QueueMessage::Gen(items)) {
                                             Ok(_) => {
-                                                statistical_send.send(Statue::TargetCount).unwrap();
                                                 drop(item)
                                             }
                                             Err(_) => {
@@ -100,30 +242,58 @@ pub fn check_event(check_recv: Receiver<QueueMessage>,
                                     }
                                 }
                             }
-                            else {
-                                // set Unwrite statue
-                                match statistical_send.send(Statue::Unwrite) {
-                                    Ok(_) => {},
-                                    Err(_) => {
-                                        error!("[check_handler] send Statue::Unwrite");
-                                    }
-                                }
-                            }
                         }
+                    }
 
-
-                    } else {
-                        // set Unwrite statue
-                        match statistical_send.send(Statue::Unwrite) {
-                            Ok(_) => {},
-                            Err(_) => {
-                                error!("[check_handler] send Statue::Unwrite");
+                    // released last, after everything it led to was registered
+                    match statistical_send.send(Statue::Done(name)) {
+                        Ok(_) => {},
+                        Err(_) => {
+                            error!("[check_handler] send Statue::Done");
+                        }
+                    }
+                }
+                QueueMessage::Complete(domain, total) => {
+                    if o.sampler.as_ref().map(|s| s.contains(domain.as_str())).unwrap_or(false) {
+                        // a stopped scan keeps the sample open in its checkpoint to take it again
+                        if !o.stop.load(Ordering::SeqCst) {
+                            let result = o.sampler.as_mut().and_then(|s| s.generated(domain.as_str(), total));
+                            if let Some(r) = result {
+                                sampled(&mut o, r, &gen_send, &statistical_send);
                             }
                         }
+                        send_statue(&statistical_send, Statue::Expanded(domain, 0), &o.stop);
+                    } else if let Some(c) = o.checkpointer.as_mut() {
+                        c.generated(domain.as_str(), total);
                     }
                 }
                 QueueMessage::Terminate => {
                     debug!("[check_handler] Terminate");
+                    if let Some(p) = o.permutator.as_ref() {
+                        info!("Generated {} permutations", p.generated());
+                    }
+                    if let Some(g) = o.grower.as_ref() {
+                        info!("Promoted {} labels into the depth dict", g.promoted().len());
+                        if let Err(e) = g.save(&o.depth_dict) {
+                            error!("[check_handler] save depth dict {:?}", e);
+                        }
+                    }
+                    // an interrupted scan keeps its checkpoint to be resumed
+                    if let Some(c) = o.checkpointer.as_mut() {
+                        if !o.stop.load(Ordering::SeqCst) {
+                            c.remove();
+                        } else if let Err(e) = c.save(&expanded) {
+                            error!("[check_handler] save checkpoint {:?}", e);
+                        } else {
+                            info!("Checkpoint saved, continue with --resume");
+                        }
+                    }
+                    if let Some(l) = o.learner.as_ref() {
+                        info!("Learned {} words", l.len());
+                        if let Err(e) = l.save() {
+                            error!("[check_handler] save learned words {:?}", e);
+                        }
+                    }
                     match statistical_send.send(Statue::Terminate) {
                         Ok(_) => {
                             debug!("[check_handler ] statistical_send Terminate");
@@ -141,10 +311,31 @@ pub fn check_event(check_recv: Receiver<QueueMessage>,
     })
 }
 
-fn gen_result(domain: &str, collect: &Vec<String>) -> ResultsSubDomain {
-    ResultsSubDomain {
-        subdomain: domain.trim_end_matches('.').to_owned(),
-        collect: collect.to_vec()
+fn gen_result(domain: &str, collect: &Vec<String>, excluded: bool) -> ResultsSubDomain {
+    ResultsSubDomain::new(domain, collect.to_vec(), excluded)
+}
+
+fn track(checkpointer: &mut Option<Checkpointer>, items: &GenItem, sub: &str, sample: bool) {
+    if let Some(c) = checkpointer.as_mut() {
+        c.start(items.domain.as_str(), sub, items.depth, sample, items.skip);
+    }
+}
+
+// Names already reported are dropped.
+fn send_result(result_send: &SyncSender<ResultsSubDomain>, reported: &mut HashSet<String>,
+               checkpointer: &mut Option<Checkpointer>, result: ResultsSubDomain) {
+    if !reported.insert(result.subdomain.to_owned()) {
+        debug!("[check_handler] already reported {}", result.subdomain);
+        return
+    }
+    if let Some(c) = checkpointer.as_mut() {
+        c.written(&result);
+    }
+    match result_send.send(result) {
+        Ok(_) => {}
+        Err(_) => {
+            error!("[check_handler] send result_send");
+        }
     }
 }
 
@@ -153,13 +344,6 @@ fn check_depth(item_depth: usize, depth: usize) -> bool {
     true
 }
 
-fn check_collect(collect: &Option<Vec<String>>) -> bool {
-    if collect.is_some() && !collect.as_ref().unwrap().is_empty(){
-        return true
-    }
-    false
-}
-
 fn check_wildcards(wildcards: &Wildcards, collect: &Vec<String>) -> bool {
 
     if wildcards.lists.is_empty() {
@@ -174,6 +358,135 @@ fn check_wildcards(wildcards: &Wildcards, collect: &Vec<String>) -> bool {
     true
 }
 
+// Register the expansion of `item` with the candidates the gen thread will emit for it: the rest of
+// the dictionary selected for it, plus the learned words appended to every
+// expansion.
+fn extend_gen(dicts: &DictSet, item: &GenItem, learner: &Option<Learner>, statistical_send: &Sender<Statue>,
+              stop: &AtomicBool) {
+    let mut n = dicts.select(item.domain.as_str(), item.depth).len().saturating_sub(item.skip);
+    if let Some(l) = learner.as_ref() {
+        n += l.len();
+    }
+    send_statue(statistical_send, Statue::Expand(item.domain.to_owned(), n), stop);
+}
+
+fn learn_words(learner: &mut Option<Learner>, checkpointer: &mut Option<Checkpointer>, item: &Item,
+               collect: &Vec<String>, gen_send: &Sender<QueueMessage>, statistical_send: &Sender<Statue>,
+               stop: &AtomicBool) {
+    let l = match learner.as_mut() {
+        Some(l) => l,
+        None => return,
+    };
+
+    l.observe(item.sub.as_str());
+    let mut words = l.learn(item.subdomain.as_str());
+    for name in collect {
+        words.extend(l.learn(name.as_str()));
+    }
+    if words.is_empty() {
+        return
+    }
+
+    debug!("[check_handler] learned {:?}", words);
+    // learned words go below every target, so the expansion belongs to none
+    send_statue(statistical_send, Statue::Expand(String::new(), words.len() * l.targets().len()), stop);
+    if let Some(c) = checkpointer.as_mut() {
+        c.learned(&words);
+        for t in l.targets() {
+            words.iter().for_each(|w| c.queued(&gen_item(t.as_str(), w.as_str(), 1)));
+        }
+    }
+    match gen_send.send(QueueMessage::Learned(words)) {
+        Ok(_) => {}
+        Err(_) => {
+            error!("[check_handler] send gen_send");
+        }
+    }
+}
+
+// Names found below a label prove it has children: promote the label and,
+// if its subtree was not brute forced yet, launch it now.
+fn grow_depth(o: &mut CheckOptions, expanded: &mut HashSet<String>, item: &Item, collect: &Vec<String>,
+              gen_send: &Sender<QueueMessage>, statistical_send: &Sender<Statue>) {
+    let g = match o.grower.as_mut() {
+        Some(g) => g,
+        None => return,
+    };
+
+    let mut parents = g.parents(item.subdomain.as_str());
+    for name in collect {
+        parents.extend(g.parents(name.as_str()));
+    }
+
+    for (domain, sub, parent_depth) in parents {
+        g.promote(sub.as_str(), &mut o.depth_dict);
+        if !check_depth(parent_depth, o.depth) || expanded.contains(&domain) || o.exclude.covers(&domain)
+            || o.psl.is_public_suffix(&domain) {
+            continue
+        }
+
+        expanded.insert(domain.to_owned());
+        let items = GenItem {
+            domain,
+            depth: parent_depth + 1,
+            skip: 0,
+        };
+        extend_gen(&o.dicts, &items, &o.learner, statistical_send, &o.stop);
+        track(&mut o.checkpointer, &items, sub.as_str(), false);
+        match gen_send.send(QueueMessage::Gen(items)) {
+            Ok(_) => {}
+            Err(_) => {
+                error!("[check_handler] send gen_send");
+            }
+        }
+    }
+}
+
+// Hand a sample of `size` queries below `items.domain` to gen. Its queries
+// are released by gen, its decision is a unit of its own released once gen
+// reported how many queries it sent, see `QueueMessage::Complete`.
+fn send_sample(o: &mut CheckOptions, items: GenItem, sub: &str, size: usize, gen_send: &Sender<QueueMessage>,
+               statistical_send: &Sender<Statue>) {
+    let items = GenItem { skip: 0, ..items };
+    send_statue(statistical_send, Statue::Expand(items.domain.to_owned(), size), &o.stop);
+    send_statue(statistical_send, Statue::Expand(items.domain.to_owned(), 0), &o.stop);
+    track(&mut o.checkpointer, &items, sub, true);
+    match gen_send.send(QueueMessage::Sample(items, size)) {
+        Ok(_) => {}
+        Err(_) => {
+            error!("[check_handler] send gen_send");
+        }
+    }
+}
+
+// The sample below `r.domain` decided: a passed one goes on to the rest of
+// the dictionary, skipping the candidates it already queried.
+fn sampled(o: &mut CheckOptions, r: SampleResult, gen_send: &Sender<QueueMessage>, statistical_send: &Sender<Statue>) {
+    debug!("[check_handler] sample {} hits {}/{}", r.domain, r.hits, r.size);
+    if let Some(c) = o.checkpointer.as_mut() {
+        c.sampled(r.domain.as_str(), r.passed);
+    }
+    if !r.passed {
+        return
+    }
+
+    if let Some(g) = o.grower.as_mut() {
+        g.promote(r.sub.as_str(), &mut o.depth_dict);
+    }
+    let items = GenItem {
+        domain: r.domain,
+        depth: r.depth + 1,
+        skip: r.size,
+    };
+    extend_gen(&o.dicts, &items, &o.learner, statistical_send, &o.stop);
+    match gen_send.send(QueueMessage::Gen(items)) {
+        Ok(_) => {}
+        Err(_) => {
+            error!("[check_handler] send gen_send");
+        }
+    }
+}
+
 fn check_depth_dict(sub: &str, depth_dict: &Dict) -> bool {
     if depth_dict.len().eq(&0) {
         return false
@@ -0,0 +1,363 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde_json;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::gen_handler::Item;
+use crate::write_handler::ResultsSubDomain;
+
+/// A dictionary expansion below `domain` that has not been checkpointed as
+/// finished. Every candidate before position `done` has been checked.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Expansion {
+    pub domain: String,
+    pub sub: String,
+    pub depth: usize,
+    // still deciding by sample whether to expand, see `--depth-policy sample`
+    pub sample: bool,
+    pub done: usize,
+}
+
+/// A query queued outside of any dictionary expansion, a permutation or a
+/// learned word below a target, that has not been checked yet.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Candidate {
+    pub sub: String,
+    pub subdomain: String,
+    pub depth: usize,
+}
+
+impl Candidate {
+    pub fn item(&self) -> Item {
+        Item {
+            sub: self.sub.to_owned(),
+            subdomain: self.subdomain.to_owned(),
+            depth: self.depth,
+            collect: None,
+            sample: false,
+            index: None,
+        }
+    }
+}
+
+/// What a scan needs to continue after the process died: the domains that
+/// were expanded, how far each expansion got, the words learned and the
+/// other queries still pending, and the results written so far.
+///
+/// The results are appended to `<FILE>.results` as they are found, one JSON
+/// object per line, the rest is rewritten at every save.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Checkpoint {
+    pub expanded: Vec<String>,
+    pub pending: Vec<Expansion>,
+    #[serde(default)]
+    pub learned: Vec<String>,
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    // checkpoints of older versions kept the results inline
+    #[serde(default, skip_serializing)]
+    pub results: Vec<ResultsSubDomain>,
+}
+
+impl Checkpoint {
+    pub fn load(filename: &str) -> io::Result<Self> {
+        let f = File::open(filename)?;
+        let mut checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(f))?;
+
+        let f = match File::open(results_file(filename)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(checkpoint),
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(f).lines() {
+            // the last line may be cut short by a crash
+            match serde_json::from_str(line?.as_str()) {
+                Ok(r) => checkpoint.results.push(r),
+                Err(e) => warn!("[checkpoint] load {} msg: {:?}", results_file(filename), e),
+            }
+        }
+        Ok(checkpoint)
+    }
+}
+
+fn results_file(filename: &str) -> String {
+    filename.to_owned() + ".results"
+}
+
+// Completion of one expansion. Queries finish out of order, so positions
+// beyond the first gap are kept until the gap is filled.
+#[derive(Clone, Debug)]
+struct Tracker {
+    sub: String,
+    depth: usize,
+    sample: bool,
+    next: usize,
+    done: BTreeSet<usize>,
+    // number of candidates, known once gen has generated all of them
+    total: Option<usize>,
+}
+
+impl Tracker {
+    fn finished(&self) -> bool {
+        self.total.map(|t| self.next >= t).unwrap_or(false)
+    }
+}
+
+/// Tracks the progress of `check_event` and saves it every `interval`.
+#[derive(Debug)]
+pub struct Checkpointer {
+    filename: String,
+    interval: Duration,
+    last: Instant,
+    expansions: HashMap<String, Tracker>,
+    learned: Vec<String>,
+    candidates: HashMap<String, Candidate>,
+    results: Option<BufWriter<File>>,
+}
+
+impl Checkpointer {
+    /// Start from `resume`, an empty checkpoint for a new scan. The results
+    /// file is rewritten with the results of `resume`.
+    pub fn new(filename: &str, interval: Duration, resume: &Checkpoint) -> Self {
+        let results = match create_results(filename, &resume.results) {
+            Ok(w) => Some(w),
+            Err(e) => {
+                warn!("[checkpoint] create {} msg: {:?}", results_file(filename), e);
+                None
+            }
+        };
+        let mut c = Self {
+            filename: filename.to_string(),
+            interval,
+            last: Instant::now(),
+            expansions: HashMap::new(),
+            learned: resume.learned.clone(),
+            candidates: resume.candidates.iter().map(|c| (c.subdomain.to_owned(), c.clone())).collect(),
+            results,
+        };
+        for e in resume.pending.iter() {
+            c.start(e.domain.as_str(), e.sub.as_str(), e.depth, e.sample, e.done);
+        }
+        c
+    }
+
+    /// Track the expansion below `domain`, whose first `done` candidates are already checked.
+    pub fn start(&mut self, domain: &str, sub: &str, depth: usize, sample: bool, done: usize) {
+        self.expansions.insert(domain.to_string(), Tracker {
+            sub: sub.to_string(),
+            depth,
+            sample,
+            next: done,
+            done: BTreeSet::new(),
+            total: None,
+        });
+    }
+
+    /// Gen has generated all `total` candidates of the expansion below `domain`,
+    /// it is dropped from the checkpoint once they are all checked.
+    pub fn generated(&mut self, domain: &str, total: usize) {
+        let finished = match self.expansions.get_mut(domain) {
+            Some(t) => {
+                t.total = Some(total);
+                t.finished()
+            }
+            None => false,
+        };
+        if finished {
+            self.expansions.remove(domain);
+        }
+    }
+
+    /// Words learned and broadcast below every target.
+    pub fn learned(&mut self, words: &[String]) {
+        self.learned.extend(words.iter().cloned());
+    }
+
+    /// A query without a position in an expansion was queued.
+    pub fn queued(&mut self, item: &Item) {
+        self.candidates.insert(item.subdomain.to_owned(), Candidate {
+            sub: item.sub.to_owned(),
+            subdomain: item.subdomain.to_owned(),
+            depth: item.depth,
+        });
+    }
+
+    /// The sample below `domain` finished: it either goes on to the full
+    /// expansion or nothing is left to do.
+    pub fn sampled(&mut self, domain: &str, passed: bool) {
+        match self.expansions.get_mut(domain) {
+            Some(t) if passed => t.sample = false,
+            Some(_) => { self.expansions.remove(domain); }
+            None => {}
+        }
+    }
+
+    /// Record that a generated candidate has been checked.
+    pub fn complete(&mut self, item: &Item) {
+        let index = match item.index {
+            Some(i) => i,
+            None => {
+                self.candidates.remove(&item.subdomain);
+                return
+            }
+        };
+        // `subdomain` is `sub` joined to the expanded domain
+        let domain = match item.subdomain.get(item.sub.len() + 1..) {
+            Some(d) => d,
+            None => return,
+        };
+        let finished = match self.expansions.get_mut(domain) {
+            Some(t) => {
+                t.done.insert(index);
+                while t.done.remove(&t.next) {
+                    t.next += 1;
+                }
+                t.finished()
+            }
+            None => false,
+        };
+        if finished {
+            self.expansions.remove(domain);
+        }
+    }
+
+    /// Append a result to the results file, it is flushed at the next save.
+    pub fn written(&mut self, result: &ResultsSubDomain) {
+        let failed = match self.results.as_mut() {
+            Some(w) => append_result(w, result).err(),
+            None => None,
+        };
+        if let Some(e) = failed {
+            warn!("[checkpoint] write {} msg: {:?}", results_file(self.filename.as_str()), e);
+            self.results = None;
+        }
+    }
+
+    /// Save the checkpoint if `interval` has passed since the last save.
+    pub fn save_due(&mut self, expanded: &HashSet<String>) {
+        if self.last.elapsed() < self.interval {
+            return
+        }
+        if let Err(e) = self.save(expanded) {
+            warn!("[checkpoint] save {} msg: {:?}", self.filename, e);
+        }
+        self.last = Instant::now();
+    }
+
+    pub fn save(&mut self, expanded: &HashSet<String>) -> io::Result<()> {
+        // the results go first, a checkpoint never refers to results it lost
+        if let Some(w) = self.results.as_mut() {
+            w.flush()?;
+        }
+
+        let mut expanded: Vec<String> = expanded.iter().cloned().collect();
+        expanded.sort();
+        let mut pending: Vec<Expansion> = self.expansions.iter()
+            .map(|(domain, t)| Expansion {
+                domain: domain.to_owned(),
+                sub: t.sub.to_owned(),
+                depth: t.depth,
+                sample: t.sample,
+                done: t.next,
+            })
+            .collect();
+        pending.sort_by(|a, b| a.domain.cmp(&b.domain));
+        let mut candidates: Vec<Candidate> = self.candidates.values().cloned().collect();
+        candidates.sort_by(|a, b| a.subdomain.cmp(&b.subdomain));
+
+        let checkpoint = Checkpoint {
+            expanded,
+            pending,
+            learned: self.learned.clone(),
+            candidates,
+            results: Vec::new(),
+        };
+
+        // write aside and rename, so a crash never leaves half a checkpoint
+        let tmp = self.filename.to_owned() + ".tmp";
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(tmp.as_str())?;
+        serde_json::to_writer(BufWriter::new(file), &checkpoint)?;
+        fs::rename(tmp, self.filename.as_str())
+    }
+
+    /// The scan finished, nothing is left to resume.
+    pub fn remove(&self) {
+        let _ = fs::remove_file(self.filename.as_str());
+        let _ = fs::remove_file(results_file(self.filename.as_str()));
+    }
+}
+
+fn create_results(filename: &str, results: &[ResultsSubDomain]) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(results_file(filename))?;
+    let mut w = BufWriter::new(file);
+    for r in results {
+        append_result(&mut w, r)?;
+    }
+    w.flush()?;
+    Ok(w)
+}
+
+fn append_result<W: Write>(w: &mut W, result: &ResultsSubDomain) -> io::Result<()> {
+    serde_json::to_writer(&mut *w, result)?;
+    w.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn item(sub: &str, index: usize) -> Item {
+        Item {
+            sub: sub.to_string(),
+            subdomain: format!("{}.example.com.", sub),
+            depth: 1,
+            collect: None,
+            sample: false,
+            index: Some(index),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = TempDir::new();
+        let filename = dir.path("checkpoint.json");
+        let filename = filename.as_str();
+        let mut c = Checkpointer::new(filename, Duration::from_secs(60), &Checkpoint::default());
+        c.start("example.com.", "", 1, false, 0);
+        for (sub, index) in vec![("www", 0), ("mail", 2), ("api", 1), ("dev", 4)] {
+            c.complete(&item(sub, index));
+        }
+
+        c.start("example.org.", "", 1, false, 0);
+        c.generated("example.org.", 1);
+        c.complete(&Item { subdomain: "www.example.org.".to_string(), ..item("www", 0) });
+        c.learned(&["dev".to_string()]);
+        c.queued(&Item { index: None, ..item("web02", 0) });
+        c.written(&ResultsSubDomain::new("www.example.com.", vec!["10.0.0.1".to_string()], false));
+
+        let expanded = vec!["example.com.".to_string()].into_iter().collect();
+        c.save(&expanded).unwrap();
+        let resume = Checkpoint::load(filename).unwrap();
+        assert_eq!(resume.expanded, vec!["example.com."]);
+        // example.org. finished and was dropped
+        assert_eq!(resume.pending.len(), 1);
+        assert_eq!(resume.pending[0].done, 3);
+        assert_eq!(resume.learned, vec!["dev"]);
+        assert_eq!(resume.candidates[0].subdomain, "web02.example.com.");
+        assert_eq!(resume.results[0].subdomain, "www.example.com");
+
+        c.remove();
+    }
+}
@@ -1,105 +1,367 @@
 use std::fs::File;
-use std::process::{exit, id};
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::net::{IpAddr, SocketAddr};
+use std::process::id;
+use std::collections::{BTreeMap, HashSet};
 
+use crate::dict::collect_dict_files;
+use crate::error::Error;
+use crate::config_file::ConfigFile;
+use crate::report::Format;
+use crate::dict_set::{parse_rule, Selector};
+use crate::exclude::ExcludeMode;
+use crate::psl::SuffixPolicy;
+use crate::query::Protocol;
 use crate::logger::{init, LoggingLevel};
-use clap::{ App, Arg };
+use clap::{ App, AppSettings, Arg, ArgMatches, SubCommand };
 use colored::*;
 
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Scan,
+    Resolve,
+    Wildcard,
+    Dict,
+    Report,
+}
+
+/// How `check_event` decides whether to recurse below a found name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DepthPolicy {
+    Dict,
+    Sample,
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
+    pub mode: Mode,
     pub domain_file: String,
-    pub sub_domain_dict: String,
-    pub depth_dict_file: String,
+    pub sub_domain_dict: Vec<String>,
+    pub depth_dict_file: Vec<String>,
     pub output_file: String,
     pub depth: usize,
     pub worker: usize,
     pub retry: usize,
-    pub use_mem: f64,
+    pub protocol: Protocol,
+    pub resolvers: Vec<SocketAddr>,
+    pub rate: usize,
+    pub queue_size: usize,
     pub pid: u32,
+    pub wildcard_cache: String,
+    pub cache_ttl: u64,
+    pub refresh: bool,
+    pub dedupe: bool,
+    pub ranked: bool,
+    pub permute: bool,
+    pub permute_words: Vec<String>,
+    pub permute_cap: usize,
+    pub learn: bool,
+    pub learn_output: Option<String>,
+    pub grow_depth: bool,
+    pub depth_output: String,
+    pub passive_file: Option<String>,
+    pub dict_files: Vec<String>,
+    pub dict_output: Option<String>,
+    pub dict_rules: Vec<(Selector, Vec<String>)>,
+    pub exclude_file: Option<String>,
+    pub exclude_mode: ExcludeMode,
+    pub psl_file: Option<String>,
+    pub suffix_policy: SuffixPolicy,
+    pub registrable: bool,
+    pub checkpoint_file: String,
+    pub checkpoint_interval: u64,
+    pub resume: bool,
+    pub depth_policy: DepthPolicy,
+    pub sample_size: usize,
+    pub sample_threshold: usize,
+    pub report_files: Vec<String>,
+    pub report_output: Option<String>,
+    pub report_format: Format,
+    pub print_config: bool,
+    // the merged options as TOML, see --print-config
+    pub effective: String,
 }
 
 impl Config {
-    pub fn new() -> Self {
+    /// Parse the command line and the config file. Invalid options and
+    /// missing files are returned, the binary reports them and exits.
+    pub fn new() -> Result<Self, Error> {
         let matches = App::new("BadDNS")
             .version("1.0.1")
             .author("Link <link.messagebox@gmail.com>")
             .about("Subdomain detection system")
-            .arg(Arg::with_name("target")
-                .short("t")
-                .long("target")
-                .value_name("FILE")
-                .required(true)
-                .help("Set the target file")
-                .takes_value(true))
-            .arg(Arg::with_name("sub")
-                .short("s")
-                .long("sub")
-                .value_name("FILE")
-                .help("Set up the sub dictionary file. Default: domaindict-170W.txt")
-                .takes_value(true))
-            .arg(Arg::with_name("depth")
-                .short("d")
-                .long("depth")
-                .help("Set up the depth dictionary file. Default: depthdict.txt")
-                .value_name("FILE")
-                .takes_value(true))
-            .arg(Arg::with_name("output")
-                .short("o")
-                .value_name("FILE")
-                .long("output")
-                .help("Setting result save file. Default: baddns-output.json")
-                .takes_value(true))
-            .arg(Arg::with_name("layer")
-                .long("layer")
-                .short("l")
-                .help("Set query level domain. Default: 1")
-                .takes_value(true))
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .args(&options(SCAN))
             .arg(Arg::with_name("v")
                 .short("v")
                 .multiple(true)
-                .help("Set log display verbosity"))
-            .arg(Arg::with_name("worker")
-                .short("w")
-                .long("worker")
-                .help("Set worker number. Default: 500")
-                .takes_value(true))
-            .arg(Arg::with_name("mem")
-                .short("m")
-                .long("mem")
-                .help("Memory utilization. Default: 50%")
-                .takes_value(true))
+                .help("Set log display verbosity")
+                .global(true))
+            .arg(Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Read options from a TOML or YAML file, keyed by their long names. Flags override it")
+                .takes_value(true)
+                .global(true))
+            .arg(Arg::with_name("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Apply the [profiles.NAME] table of the config file")
+                .requires("config")
+                .takes_value(true)
+                .global(true))
+            .arg(Arg::with_name("print-config")
+                .long("print-config")
+                .help("Print the effective configuration as TOML and exit")
+                .global(true))
+            .subcommand(SubCommand::with_name("scan")
+                .about("Brute force the subdomains of the targets, the default")
+                .args(&options(SCAN)))
+            .subcommand(SubCommand::with_name("resolve")
+                .about("Resolve a list of names")
+                .args(&options(RESOLVE)))
+            .subcommand(SubCommand::with_name("wildcard")
+                .about("Only detect wildcard records and write a per-zone report")
+                .args(&options(WILDCARD)))
+            .subcommand(SubCommand::with_name("dict")
+                .about("Validate, deduplicate and merge dictionaries and show their statistics")
+                .arg(Arg::with_name("files")
+                    .value_name("FILE")
+                    .help("Dictionary files or directories (.gz/.zst supported)")
+                    .required(true)
+                    .multiple(true))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .value_name("FILE")
+                    .help("Write the cleaned, merged dictionary")
+                    .takes_value(true)))
+            .subcommand(SubCommand::with_name("report")
+                .about("Merge result files and convert them to json, txt or csv")
+                .arg(Arg::with_name("files")
+                    .value_name("FILE")
+                    .help("Result files written by scan or resolve")
+                    .required(true)
+                    .multiple(true))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .value_name("FILE")
+                    .help("Write the report to a file instead of stdout")
+                    .takes_value(true))
+                .arg(Arg::with_name("format")
+                    .short("f")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .help("json, txt (one name per line) or csv. Default: by the output extension, else json")
+                    .possible_values(&["json", "txt", "csv"])
+                    .takes_value(true)))
             .get_matches();
 
-        match matches.occurrences_of("v") {
+        // without a subcommand the top level options scan, as before subcommands existed
+        let (mode, m) = match matches.subcommand() {
+            ("scan", Some(m)) => (Mode::Scan, m),
+            ("resolve", Some(m)) => (Mode::Resolve, m),
+            ("wildcard", Some(m)) => (Mode::Wildcard, m),
+            ("dict", Some(m)) => (Mode::Dict, m),
+            ("report", Some(m)) => (Mode::Report, m),
+            _ => (Mode::Scan, &matches),
+        };
+
+        match matches.occurrences_of("v").max(m.occurrences_of("v")) {
             1 => {init(LoggingLevel::Normal); ()},
             2 => {init(LoggingLevel::Critical); ()},
             3 => {init(LoggingLevel::Debug); {}},
             0 | _ => {init(LoggingLevel::Off); ()},
         }
 
-        let target_file = matches.value_of("target").unwrap().to_string();
-        verify_file(target_file.as_str());
+        let config = m.value_of("config").or_else(|| matches.value_of("config"));
+        let file = match config {
+            Some(f) => ConfigFile::load(f, m.value_of("profile").or_else(|| matches.value_of("profile")))?,
+            None => ConfigFile::default(),
+        };
+        let opts = Options::new(m, file);
+
+        // the configuration is printed as merged, files are only checked to run
+        let print_config = matches.is_present("print-config") || m.is_present("print-config");
+        let verify = |name: &str| if print_config { Ok(()) } else { verify_file(name) };
+        let verify_dicts = |names: Vec<String>| if print_config { Ok(names) } else { verify_dict_files(names) };
+
+        let mode = if mode == Mode::Scan && opts.switch("wildcard", false)? {
+            Mode::Wildcard
+        } else {
+            mode
+        };
+
+        let (dict_files, dict_output) = match mode {
+            // clap requires the files, there is always at least one
+            Mode::Dict => (verify_dicts(m.values_of("files").into_iter().flatten().map(|s| s.to_string()).collect())?,
+                           m.value_of("output").map(|s| s.to_string())),
+            _ => (Vec::new(), None),
+        };
+
+        let report_files: Vec<String> = match mode {
+            Mode::Report => m.values_of("files").into_iter().flatten().map(|s| s.to_string()).collect(),
+            _ => Vec::new(),
+        };
+        for f in report_files.iter() {
+            verify(f.as_str())?;
+        }
+        let report_output = match mode {
+            Mode::Report => m.value_of("output").map(|s| s.to_string()),
+            _ => None,
+        };
+        let report_format = match m.value_of("format") {
+            Some(f) => Format::parse(f),
+            None => report_output.as_ref().map(|o| Format::from_filename(o.as_str())).unwrap_or(Format::Json),
+        };
+
+        let needs_target = mode != Mode::Dict && mode != Mode::Report;
+        let target_file = opts.value_opt("target")?.unwrap_or_default();
+        if needs_target && target_file.is_empty() && !print_config {
+            return Err(Error::Config("no targets, set --target or target in the config file".to_string()))
+        }
+        if needs_target && !target_file.is_empty() && target_file != "-" {
+            verify(target_file.as_str())?;
+        }
+
+        let sub_file = opts.values_or("sub", "domaindict-170W.txt")?;
+        let sub_file = match mode {
+            Mode::Scan => verify_dicts(sub_file)?,
+            _ => Vec::new(),
+        };
+
+        let depth_file = opts.values_or("depth", "depthdict.txt")?;
+        let depth_file = match mode {
+            Mode::Scan | Mode::Wildcard => verify_dicts(depth_file)?,
+            _ => Vec::new(),
+        };
+
+        // the wildcard report must not overwrite the results of a scan
+        let output_file = match mode {
+            Mode::Wildcard => opts.value("output", "baddns-wildcards-report.json")?,
+            _ => opts.value("output", "baddns-output.json")?,
+        };
+
+        let layer: usize = opts.number("layer", "1")?;
+
+        let worker: usize = opts.number("worker", "500")?;
+        if worker == 0 {
+            return Err(invalid("worker", "must be at least 1"))
+        }
+
+        let retry: usize = opts.number("retry", "3")?;
+
+        let protocol = match opts.choice("protocol", "tcp", &["tcp", "udp"])?.as_str() {
+            "udp" => Protocol::UDP,
+            _ => Protocol::TCP,
+        };
+
+        let resolvers = match opts.values("resolvers")? {
+            Some(v) => v.iter().map(|r| parse_resolver(r)).collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+
+        let rate: usize = opts.number("rate", "0")?;
+
+        let queue_size: usize = opts.number("queue-size", "10000")?;
+        if queue_size == 0 {
+            return Err(invalid("queue-size", "must be at least 1"))
+        }
+
+        let wildcard_cache = opts.value("cache", "baddns-wildcards.json")?;
+
+        let cache_ttl: u64 = opts.number("ttl", "86400")?;
+
+        let refresh = opts.switch("refresh", false)?;
+
+        // sub dictionaries are deduplicated like the in-memory dictionary used to be
+        let dedupe = opts.switch("dedupe", true)?;
+
+        let ranked = opts.switch("ranked", false)?;
+
+        let permute = opts.switch("permute", false)?;
+
+        let permute_words = match opts.values("permute-words")? {
+            Some(v) => verify_dicts(v)?,
+            None => depth_file.clone(),
+        };
+
+        let permute_cap: usize = opts.number("permute-cap", "100000")?;
+
+        let learn = opts.switch("learn", false)?;
+
+        let learn_output = opts.value_opt("learn-output")?;
+
+        let grow_depth = opts.switch("grow-depth", false)?;
+
+        let depth_output = opts.value("depth-output", "baddns-depthdict.txt")?;
+
+        let depth_policy = match opts.choice("depth-policy", "dict", &["dict", "sample"])?.as_str() {
+            "sample" => DepthPolicy::Sample,
+            _ => DepthPolicy::Dict,
+        };
+
+        let sample_size: usize = opts.number("sample-size", "50")?;
+
+        let sample_threshold: usize = opts.number("sample-threshold", "1")?;
+
+        let dict_rules = match opts.values("dict-rule")? {
+            Some(v) => v.iter().map(|r| verify_dict_rule(r, print_config)).collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+
+        let exclude_file = opts.value_opt("exclude")?;
+        if let Some(f) = exclude_file.as_ref() {
+            verify(f.as_str())?;
+        }
+
+        let exclude_mode = match opts.choice("exclude-mode", "drop", &["drop", "flag"])?.as_str() {
+            "flag" => ExcludeMode::Flag,
+            _ => ExcludeMode::Drop,
+        };
+
+        let psl_file = opts.value_opt("psl")?;
+        if let Some(f) = psl_file.as_ref() {
+            verify(f.as_str())?;
+        }
+
+        let suffix_policy = match opts.choice("public-suffix", "refuse", &["warn", "refuse"])?.as_str() {
+            "warn" => SuffixPolicy::Warn,
+            _ => SuffixPolicy::Refuse,
+        };
 
-        let sub_file = matches.value_of("sub").unwrap_or("domaindict-170W.txt").to_string();
-        verify_file(sub_file.as_str());
+        let registrable = opts.switch("registrable", false)?;
 
-        let depth_file = matches.value_of("depth").unwrap_or("depthdict.txt").to_string();
-        verify_file(depth_file.as_str());
+        let checkpoint_file = opts.value("checkpoint", "baddns-checkpoint.json")?;
 
-        let output_file = matches.value_of("output").unwrap_or("baddns-output.json").to_string();
+        let checkpoint_interval: u64 = opts.number("checkpoint-interval", "60")?;
 
-        let layer: usize = matches.value_of("layer").unwrap_or("1").parse().unwrap();
+        let resume = opts.switch("resume", false)?;
+        if resume {
+            verify(checkpoint_file.as_str())?;
+        }
 
-        let worker: usize = matches.value_of("worker").unwrap_or("500").parse().unwrap();
+        let passive_file = opts.value_opt("passive")?;
+        if let Some(f) = passive_file.as_ref() {
+            verify(f.as_str())?;
+        }
 
-        let retry: usize = matches.value_of("retry").unwrap_or("3").parse().unwrap();
+        // every option was looked up above, anything else in the file is a mistake
+        let unknown: Vec<&String> = opts.unknown();
+        if !unknown.is_empty() {
+            return Err(Error::Config(format!("{} unknown options {:?}", config.unwrap_or(""), unknown)))
+        }
 
-        let use_mem: f64 = matches.value_of("mem").unwrap_or("0.5").parse().unwrap();
+        let effective = opts.to_toml();
 
         let pid = id();
 
-        Self {
+        Ok(Self {
+            mode,
             domain_file: target_file,
             sub_domain_dict: sub_file,
             depth_dict_file: depth_file,
@@ -107,21 +369,59 @@ impl Config {
             depth: layer,
             worker,
             retry,
-            use_mem,
-            pid
+            protocol,
+            resolvers,
+            rate,
+            queue_size,
+            pid,
+            wildcard_cache,
+            cache_ttl,
+            refresh,
+            dedupe,
+            ranked,
+            permute,
+            permute_words,
+            permute_cap,
+            learn,
+            learn_output,
+            grow_depth,
+            depth_output,
+            passive_file,
+            dict_files,
+            dict_output,
+            dict_rules,
+            exclude_file,
+            exclude_mode,
+            psl_file,
+            suffix_policy,
+            registrable,
+            checkpoint_file,
+            checkpoint_interval,
+            resume,
+            depth_policy,
+            sample_size,
+            sample_threshold,
+            report_files,
+            report_output,
+            report_format,
+            print_config,
+            effective,
+        })
+    }
 
-        }
+    pub fn get_mode(&self) -> &Mode {
+        &self.mode
     }
 
     pub fn get_target_file(&self) -> &String{
         &self.domain_file
     }
 
-    pub fn get_sub_dict_file(&self) -> &String {
+    pub fn get_sub_dict_file(&self) -> &Vec<String> {
         &self.sub_domain_dict
     }
 
-    pub fn get_depth_dict_file(&self) -> &String {
+    pub fn get_depth_dict_file(&self) -> &Vec<String> {
         &self.depth_dict_file
     }
 
@@ -141,24 +441,540 @@ impl Config {
         self.retry
     }
 
-    pub fn get_use_mem(&self) -> f64 {
-        self.use_mem
+    pub fn get_protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    pub fn get_resolvers(&self) -> &Vec<SocketAddr> {
+        &self.resolvers
+    }
+
+    pub fn get_rate(&self) -> usize {
+        self.rate
+    }
+
+    pub fn get_queue_size(&self) -> usize {
+        self.queue_size
     }
 
     pub fn get_pid(&self) -> u32 {
         self.pid
     }
+
+    pub fn get_wildcard_cache(&self) -> &String {
+        &self.wildcard_cache
+    }
+
+    pub fn get_cache_ttl(&self) -> u64 {
+        self.cache_ttl
+    }
+
+    pub fn get_refresh(&self) -> bool {
+        self.refresh
+    }
+
+    pub fn get_dedupe(&self) -> bool {
+        self.dedupe
+    }
+
+    pub fn get_ranked(&self) -> bool {
+        self.ranked
+    }
+
+    pub fn get_permute(&self) -> bool {
+        self.permute
+    }
+
+    pub fn get_permute_words(&self) -> &Vec<String> {
+        &self.permute_words
+    }
+
+    pub fn get_permute_cap(&self) -> usize {
+        self.permute_cap
+    }
+
+    pub fn get_learn(&self) -> bool {
+        self.learn
+    }
+
+    pub fn get_learn_output(&self) -> &Option<String> {
+        &self.learn_output
+    }
+
+    pub fn get_grow_depth(&self) -> bool {
+        self.grow_depth
+    }
+
+    pub fn get_depth_output(&self) -> &String {
+        &self.depth_output
+    }
+
+    pub fn get_passive_file(&self) -> &Option<String> {
+        &self.passive_file
+    }
+
+    pub fn get_dict_files(&self) -> &Vec<String> {
+        &self.dict_files
+    }
+
+    pub fn get_dict_output(&self) -> &Option<String> {
+        &self.dict_output
+    }
+
+    pub fn get_dict_rules(&self) -> &Vec<(Selector, Vec<String>)> {
+        &self.dict_rules
+    }
+
+    pub fn get_exclude_file(&self) -> &Option<String> {
+        &self.exclude_file
+    }
+
+    pub fn get_exclude_mode(&self) -> &ExcludeMode {
+        &self.exclude_mode
+    }
+
+    pub fn get_psl_file(&self) -> &Option<String> {
+        &self.psl_file
+    }
+
+    pub fn get_suffix_policy(&self) -> &SuffixPolicy {
+        &self.suffix_policy
+    }
+
+    pub fn get_registrable(&self) -> bool {
+        self.registrable
+    }
+
+    pub fn get_checkpoint_file(&self) -> &String {
+        &self.checkpoint_file
+    }
+
+    pub fn get_checkpoint_interval(&self) -> u64 {
+        self.checkpoint_interval
+    }
+
+    pub fn get_resume(&self) -> bool {
+        self.resume
+    }
+
+    pub fn get_depth_policy(&self) -> &DepthPolicy {
+        &self.depth_policy
+    }
+
+    pub fn get_sample_size(&self) -> usize {
+        self.sample_size
+    }
+
+    pub fn get_sample_threshold(&self) -> usize {
+        self.sample_threshold
+    }
+
+    pub fn get_report_files(&self) -> &Vec<String> {
+        &self.report_files
+    }
+
+    pub fn get_report_output(&self) -> &Option<String> {
+        &self.report_output
+    }
+
+    pub fn get_report_format(&self) -> &Format {
+        &self.report_format
+    }
+
+    pub fn get_print_config(&self) -> bool {
+        self.print_config
+    }
+
+    pub fn get_effective(&self) -> &String {
+        &self.effective
+    }
 }
 
-fn verify_file(name: &str) {
-    match File::open(name) {
-        Ok(_) => {}
-        Err(e) => {
-            error!("{} {}", name, e);
-            info!("For more information try --help");
-            exit(1);
+// Looks an option up on the command line, then in the config file, then
+// takes the default. The effective values are kept for --print-config, an
+// invalid value is returned as an error naming its key.
+struct Options<'a> {
+    matches: &'a ArgMatches<'a>,
+    file: ConfigFile,
+    effective: RefCell<BTreeMap<String, toml::Value>>,
+    seen: RefCell<HashSet<String>>,
+}
+
+impl<'a> Options<'a> {
+    fn new(matches: &'a ArgMatches<'a>, file: ConfigFile) -> Self {
+        Self {
+            matches,
+            file,
+            effective: RefCell::new(BTreeMap::new()),
+            seen: RefCell::new(HashSet::new()),
         }
     }
+
+    fn value_opt(&self, name: &str) -> Result<Option<String>, Error> {
+        self.seen.borrow_mut().insert(name.to_string());
+        let v = match self.matches.value_of(name) {
+            Some(v) => v.to_string(),
+            None => match self.file.value(name) {
+                Some(v) => v,
+                None if self.file.contains(name) => return Err(invalid(name, "expects a single value")),
+                None => return Ok(None),
+            },
+        };
+        self.record(name, v.as_str());
+        Ok(Some(v))
+    }
+
+    fn value(&self, name: &str, default: &str) -> Result<String, Error> {
+        match self.value_opt(name)? {
+            Some(v) => Ok(v),
+            None => {
+                self.record(name, default);
+                Ok(default.to_string())
+            }
+        }
+    }
+
+    fn number<T: FromStr>(&self, name: &str, default: &str) -> Result<T, Error> {
+        let v = self.value(name, default)?;
+        v.parse().map_err(|_| invalid(name, format!("{:?} is not a number", v)))
+    }
+
+    // clap checks the choices on the command line, not in the config file.
+    fn choice(&self, name: &str, default: &str, choices: &[&str]) -> Result<String, Error> {
+        let v = self.value(name, default)?;
+        if !choices.contains(&v.as_str()) {
+            return Err(invalid(name, format!("{:?} is not one of {}", v, choices.join(", "))))
+        }
+        Ok(v)
+    }
+
+    fn record(&self, name: &str, v: &str) {
+        let t = match v.parse::<i64>() {
+            Ok(n) => toml::Value::Integer(n),
+            Err(_) => toml::Value::String(v.to_string()),
+        };
+        self.effective.borrow_mut().insert(name.to_string(), t);
+    }
+
+    fn values(&self, name: &str) -> Result<Option<Vec<String>>, Error> {
+        self.seen.borrow_mut().insert(name.to_string());
+        let v = match self.matches.values_of(name) {
+            Some(v) => v.map(|s| s.to_string()).collect(),
+            None => match self.file.values(name) {
+                Some(v) => v,
+                None if self.file.contains(name) => return Err(invalid(name, "expects a list of values")),
+                None => return Ok(None),
+            },
+        };
+        let t = v.iter().map(|s| toml::Value::String(s.to_owned())).collect();
+        self.effective.borrow_mut().insert(name.to_string(), toml::Value::Array(t));
+        Ok(Some(v))
+    }
+
+    fn values_or(&self, name: &str, default: &str) -> Result<Vec<String>, Error> {
+        match self.values(name)? {
+            Some(v) => Ok(v),
+            None => {
+                let t = vec![toml::Value::String(default.to_string())];
+                self.effective.borrow_mut().insert(name.to_string(), toml::Value::Array(t));
+                Ok(vec![default.to_string()])
+            }
+        }
+    }
+
+    // A boolean option, --NAME and --no-NAME override the config file.
+    fn switch(&self, name: &str, default: bool) -> Result<bool, Error> {
+        self.seen.borrow_mut().insert(name.to_string());
+        let v = if self.matches.is_present(name) {
+            true
+        } else if self.matches.is_present(format!("no-{}", name).as_str()) {
+            false
+        } else {
+            match self.file.flag(name) {
+                Some(v) => v,
+                None if self.file.contains(name) => return Err(invalid(name, "expects true or false")),
+                None => default,
+            }
+        };
+        self.effective.borrow_mut().insert(name.to_string(), toml::Value::Boolean(v));
+        Ok(v)
+    }
+
+    fn unknown(&self) -> Vec<&String> {
+        let seen = self.seen.borrow();
+        self.file.keys().filter(|k| !seen.contains(*k)).collect()
+    }
+
+    fn to_toml(&self) -> String {
+        let effective: toml::value::Table = self.effective.borrow().clone().into_iter().collect();
+        toml::to_string(&toml::Value::Table(effective)).unwrap_or_default()
+    }
+}
+
+// Options of `scan`, the other subcommands take the ones they use.
+const SCAN: &[&str] = &["target", "sub", "depth", "output", "layer", "worker", "retry", "protocol", "resolvers", "rate", "queue-size", "cache",
+    "ttl", "refresh", "dedupe", "no-dedupe", "ranked", "permute", "permute-words", "permute-cap", "learn", "learn-output",
+    "grow-depth", "depth-output", "passive", "dict-rule", "exclude", "exclude-mode", "psl",
+    "public-suffix", "registrable", "checkpoint", "checkpoint-interval", "resume", "depth-policy",
+    "sample-size", "sample-threshold", "wildcard"];
+
+const WILDCARD: &[&str] = &["target", "depth", "output", "worker", "resolvers", "cache", "ttl", "refresh",
+    "exclude", "psl", "public-suffix", "registrable"];
+
+const RESOLVE: &[&str] = &["target", "output", "worker", "retry", "protocol", "resolvers", "exclude"];
+
+// Flags a config file can turn on, the second name turns them back off.
+// `dedupe` is on by default, its `no-dedupe` is an option of its own.
+const NEGATED: &[(&str, &str)] = &[("refresh", "no-refresh"), ("ranked", "no-ranked"), ("permute", "no-permute"),
+    ("learn", "no-learn"), ("grow-depth", "no-grow-depth"), ("registrable", "no-registrable"),
+    ("resume", "no-resume"), ("wildcard", "no-wildcard")];
+
+fn options(names: &[&str]) -> Vec<Arg<'static, 'static>> {
+    let mut args = Vec::new();
+    for n in names {
+        args.push(option(n));
+        if let Some((name, no)) = NEGATED.iter().find(|(name, _)| name == n) {
+            args.push(Arg::with_name(no)
+                .long(no)
+                .conflicts_with(name)
+                .help("Turn the flag off, e.g. when the config file turns it on"));
+        }
+    }
+    args
+}
+
+fn option(name: &str) -> Arg<'static, 'static> {
+    match name {
+        "target" => Arg::with_name("target")
+            .short("t")
+            .long("target")
+            .value_name("FILE")
+            .help("Set the target file, - reads targets from stdin. URLs, ports, *. prefixes and # comments are accepted")
+            .takes_value(true),
+        "sub" => Arg::with_name("sub")
+            .short("s")
+            .long("sub")
+            .value_name("FILE")
+            .help("Set up the sub dictionary files or directories (.gz/.zst supported). Default: domaindict-170W.txt")
+            .multiple(true)
+            .use_delimiter(true)
+            .takes_value(true),
+        "depth" => Arg::with_name("depth")
+            .short("d")
+            .long("depth")
+            .help("Set up the depth dictionary files or directories (.gz/.zst supported). Default: depthdict.txt")
+            .value_name("FILE")
+            .multiple(true)
+            .use_delimiter(true)
+            .takes_value(true),
+        "output" => Arg::with_name("output")
+            .short("o")
+            .value_name("FILE")
+            .long("output")
+            .help("Setting result save file. Default: baddns-output.json, baddns-wildcards-report.json for the wildcard report")
+            .takes_value(true),
+        "layer" => Arg::with_name("layer")
+            .long("layer")
+            .short("l")
+            .help("Set query level domain. Default: 1")
+            .takes_value(true),
+        "worker" => Arg::with_name("worker")
+            .short("w")
+            .long("worker")
+            .help("Set worker number. Default: 500")
+            .takes_value(true),
+        "retry" => Arg::with_name("retry")
+            .long("retry")
+            .value_name("NUMBER")
+            .help("Times a name is queried again when no server answered. Default: 3")
+            .takes_value(true),
+        "protocol" => Arg::with_name("protocol")
+            .long("protocol")
+            .value_name("PROTOCOL")
+            .possible_values(&["tcp", "udp"])
+            .help("Query the resolvers over tcp or udp. Default: tcp")
+            .takes_value(true),
+        "resolvers" => Arg::with_name("resolvers")
+            .long("resolvers")
+            .value_name("IP:PORT")
+            .help("DNS servers to query and to probe for wildcards, the port defaults to 53. Default: the built-in public resolvers")
+            .multiple(true)
+            .use_delimiter(true)
+            .takes_value(true),
+        "rate" => Arg::with_name("rate")
+            .long("rate")
+            .value_name("NUMBER")
+            .help("Names queried per second at most, 0 does not limit. Default: 0")
+            .takes_value(true),
+        "queue-size" => Arg::with_name("queue-size")
+            .long("queue-size")
+            .value_name("NUMBER")
+            .help("Capacity of the queues between pipeline stages, generation blocks when it is full. Default: 10000")
+            .takes_value(true),
+        "cache" => Arg::with_name("cache")
+            .long("cache")
+            .value_name("FILE")
+            .help("Set the wildcard cache file. Default: baddns-wildcards.json")
+            .takes_value(true),
+        "ttl" => Arg::with_name("ttl")
+            .long("ttl")
+            .value_name("SECONDS")
+            .help("Wildcard cache expiry in seconds. Default: 86400")
+            .takes_value(true),
+        "refresh" => Arg::with_name("refresh")
+            .long("refresh")
+            .help("Ignore the wildcard cache and probe every target again"),
+        "dedupe" => Arg::with_name("dedupe")
+            .long("dedupe")
+            .help("Deduplicate the sub dictionary through an on-disk index, the default"),
+        "no-dedupe" => Arg::with_name("no-dedupe")
+            .long("no-dedupe")
+            .conflicts_with("dedupe")
+            .help("Stream the sub dictionary as it is, without sorting or deduplicating it"),
+        "ranked" => Arg::with_name("ranked")
+            .long("ranked")
+            .help("Order the sub dictionary by its weight column, highest first (<FILE>.rank)"),
+        "permute" => Arg::with_name("permute")
+            .long("permute")
+            .help("Generate permutations of discovered subdomains"),
+        "permute-words" => Arg::with_name("permute-words")
+            .long("permute-words")
+            .value_name("FILE")
+            .help("Set the permutation word list. Default: the depth dictionary")
+            .multiple(true)
+            .use_delimiter(true)
+            .takes_value(true),
+        "permute-cap" => Arg::with_name("permute-cap")
+            .long("permute-cap")
+            .value_name("NUMBER")
+            .help("Maximum number of permutations for the whole scan. Default: 100000")
+            .takes_value(true),
+        "learn" => Arg::with_name("learn")
+            .long("learn")
+            .help("Learn new words from discovered names and try them on every target"),
+        "learn-output" => Arg::with_name("learn-output")
+            .long("learn-output")
+            .value_name("FILE")
+            .help("Save the learned words to a dictionary file")
+            .takes_value(true),
+        "grow-depth" => Arg::with_name("grow-depth")
+            .long("grow-depth")
+            .help("Promote labels found to have children into the depth dictionary"),
+        "depth-output" => Arg::with_name("depth-output")
+            .long("depth-output")
+            .value_name("FILE")
+            .help("Save the grown depth dictionary. Default: baddns-depthdict.txt")
+            .takes_value(true),
+        "passive" => Arg::with_name("passive")
+            .long("passive")
+            .value_name("FILE")
+            .help("Known hostnames used to grow the depth dictionary")
+            .takes_value(true),
+        "dict-rule" => Arg::with_name("dict-rule")
+            .long("dict-rule")
+            .value_name("RULE")
+            .help("Use another sub dictionary for some targets or depths: [TARGET][@DEPTH]=FILE[,FILE], e.g. 2+=small.txt or *.corp.example.com=corp.txt. The first matching rule wins")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true),
+        "exclude" => Arg::with_name("exclude")
+            .long("exclude")
+            .value_name("FILE")
+            .help("Out-of-scope file: domains (with their subdomains), *.domain, CIDR ranges or IPs, one per line")
+            .takes_value(true),
+        "exclude-mode" => Arg::with_name("exclude-mode")
+            .long("exclude-mode")
+            .value_name("MODE")
+            .possible_values(&["drop", "flag"])
+            .help("Drop results resolving into excluded ranges, or keep them flagged as excluded. Default: drop")
+            .takes_value(true),
+        "psl" => Arg::with_name("psl")
+            .long("psl")
+            .value_name("FILE")
+            .help("Use this Public Suffix List instead of the embedded one")
+            .takes_value(true),
+        "public-suffix" => Arg::with_name("public-suffix")
+            .long("public-suffix")
+            .value_name("POLICY")
+            .possible_values(&["warn", "refuse"])
+            .help("Warn about or refuse targets that are public suffixes. Default: refuse")
+            .takes_value(true),
+        "registrable" => Arg::with_name("registrable")
+            .long("registrable")
+            .help("Collapse every target to its registrable domain"),
+        "checkpoint" => Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .value_name("FILE")
+            .help("Set the checkpoint file. Default: baddns-checkpoint.json")
+            .takes_value(true),
+        "checkpoint-interval" => Arg::with_name("checkpoint-interval")
+            .long("checkpoint-interval")
+            .value_name("SECONDS")
+            .help("Save a checkpoint every SECONDS, 0 disables checkpoints. Default: 60")
+            .takes_value(true),
+        "resume" => Arg::with_name("resume")
+            .long("resume")
+            .help("Continue the scan saved in the checkpoint file"),
+        "depth-policy" => Arg::with_name("depth-policy")
+            .long("depth-policy")
+            .value_name("POLICY")
+            .possible_values(&["dict", "sample"])
+            .help("Recurse by the depth dictionary or by sampling each new parent. Default: dict")
+            .takes_value(true),
+        "sample-size" => Arg::with_name("sample-size")
+            .long("sample-size")
+            .value_name("NUMBER")
+            .help("Number of top dictionary candidates queried per sample. Default: 50")
+            .takes_value(true),
+        "sample-threshold" => Arg::with_name("sample-threshold")
+            .long("sample-threshold")
+            .value_name("NUMBER")
+            .help("Sample hits required to brute force the subtree. Default: 1")
+            .takes_value(true),
+        "wildcard" => Arg::with_name("wildcard")
+            .long("wildcard")
+            .help("Only detect wildcard records and write a per-zone report"),
+        _ => unreachable!("unknown option {}", name),
+    }
+}
+
+fn invalid<T: std::fmt::Display>(name: &str, msg: T) -> Error {
+    Error::Config(format!("{} {}", name, msg))
+}
+
+// `IP:PORT`, or a bare IP on port 53.
+fn parse_resolver(s: &str) -> Result<SocketAddr, Error> {
+    let s = s.trim();
+    match s.parse::<SocketAddr>() {
+        Ok(addr) => Ok(addr),
+        Err(_) => s.parse::<IpAddr>()
+            .map(|ip| SocketAddr::new(ip, 53))
+            .map_err(|_| invalid("resolvers", format!("{:?} is not an IP:PORT", s))),
+    }
+}
+
+fn verify_file(name: &str) -> Result<(), Error> {
+    match File::open(name) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::io(name, e)),
+    }
+}
+
+fn verify_dict_files(names: Vec<String>) -> Result<Vec<String>, Error> {
+    match collect_dict_files(&names) {
+        Ok(files) if !files.is_empty() => Ok(files),
+        Ok(_) => Err(Error::Config(format!("{:?} no dictionary files", names))),
+        Err(e) => Err(Error::io(names.join(",").as_str(), e)),
+    }
+}
+
+// The files are not checked when `print_config` is set.
+fn verify_dict_rule(rule: &str, print_config: bool) -> Result<(Selector, Vec<String>), Error> {
+    match parse_rule(rule) {
+        Some((selector, files)) if print_config => Ok((selector, files)),
+        Some((selector, files)) => Ok((selector, verify_dict_files(files)?)),
+        None => Err(Error::Config(format!("dict-rule {:?} is invalid", rule))),
+    }
 }
 
 pub fn show_logo() {
@@ -169,3 +985,30 @@ pub fn show_logo() {
     println!("{}", "|____/ \\__,_|\\__,_|____/|_| \\_|____/".red());
     println!("\n{}", "                            ".bold().red());
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let m = App::new("baddns").args(&options(SCAN)).get_matches_from(vec!["baddns", "--no-refresh", "-w", "20"]);
+        let text = "refresh = true\nlearn = true\nworker = 500\nretry = \"many\"\nexclude-mode = \"bogus\"\nsub = { a = 1 }\n";
+        let opts = Options::new(&m, ConfigFile::parse(text, false, None).unwrap());
+        assert_eq!(opts.switch("refresh", false).unwrap(), false);
+        assert_eq!(opts.switch("learn", false).unwrap(), true);
+        assert_eq!(opts.number::<usize>("worker", "1").unwrap(), 20);
+        assert_eq!(opts.number::<usize>("retry", "3").is_err(), true);
+        assert_eq!(opts.choice("exclude-mode", "drop", &["drop", "flag"]).is_err(), true);
+        assert_eq!(opts.values("sub").is_err(), true);
+        assert_eq!(opts.unknown().is_empty(), true);
+    }
+
+    #[test]
+    fn test_parse_resolver() {
+        assert_eq!(parse_resolver("127.0.0.1:5353").unwrap(), "127.0.0.1:5353".parse().unwrap());
+        assert_eq!(parse_resolver(" 1.1.1.1").unwrap(), "1.1.1.1:53".parse().unwrap());
+        assert_eq!(parse_resolver("[::1]:53").unwrap(), "[::1]:53".parse().unwrap());
+        assert_eq!(parse_resolver("dns.example.com").is_err(), true);
+    }
+}
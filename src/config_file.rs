@@ -0,0 +1,111 @@
+use std::fs;
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+
+/// Options read from a TOML or YAML file (by extension `.yaml`/`.yml`).
+///
+/// Keys are the long command line option names. A selected profile,
+/// `[profiles.NAME]`, overrides the top level keys:
+///
+/// ```toml
+/// worker = 500
+/// sub = ["domaindict-170W.txt"]
+///
+/// [profiles.fast]
+/// worker = 2000
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigFile {
+    values: BTreeMap<String, toml::Value>,
+}
+
+impl ConfigFile {
+    pub fn load(filename: &str, profile: Option<&str>) -> Result<Self, Error> {
+        let text = fs::read_to_string(filename).map_err(|e| Error::io(filename, e))?;
+        let yaml = filename.ends_with(".yaml") || filename.ends_with(".yml");
+        Self::parse(text.as_str(), yaml, profile)
+            .map_err(|e| Error::Config(format!("{} {}", filename, e)))
+    }
+
+    pub fn parse(text: &str, yaml: bool, profile: Option<&str>) -> Result<Self, String> {
+        let root: toml::Value = if yaml {
+            serde_yaml::from_str(text).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(text).map_err(|e| e.to_string())?
+        };
+        let mut values = match root {
+            toml::Value::Table(t) => t,
+            _ => return Err("expected a table of options".to_string()),
+        };
+
+        let mut profiles = match values.remove("profiles") {
+            Some(toml::Value::Table(p)) => p,
+            Some(_) => return Err("profiles must be a table".to_string()),
+            None => toml::value::Table::new(),
+        };
+        if let Some(name) = profile {
+            match profiles.remove(name) {
+                Some(toml::Value::Table(p)) => values.extend(p),
+                Some(_) => return Err(format!("profile {} must be a table", name)),
+                None => return Err(format!("no profile {}", name)),
+            }
+        }
+
+        Ok(Self {
+            values: values.into_iter().collect(),
+        })
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.values.keys()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<String> {
+        match self.values.get(name)? {
+            toml::Value::String(s) => Some(s.to_owned()),
+            toml::Value::Array(_) | toml::Value::Table(_) => None,
+            v => Some(v.to_string()),
+        }
+    }
+
+    /// A single value is taken as a list of one.
+    pub fn values(&self, name: &str) -> Option<Vec<String>> {
+        match self.values.get(name)? {
+            toml::Value::Array(a) => Some(a.iter().map(|v| match v {
+                toml::Value::String(s) => s.to_owned(),
+                v => v.to_string(),
+            }).collect()),
+            _ => self.value(name).map(|v| vec![v]),
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        self.values.get(name)?.as_bool()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "worker = 500\nsub = [\"a.txt\", \"b.txt\"]\nrefresh = true\n\n[profiles.fast]\nworker = 2000\n";
+        let c = ConfigFile::parse(text, false, Some("fast")).unwrap();
+        assert_eq!(c.value("worker"), Some("2000".to_string()));
+        assert_eq!(c.values("sub"), Some(vec!["a.txt".to_string(), "b.txt".to_string()]));
+        assert_eq!(c.flag("refresh"), Some(true));
+        assert_eq!(ConfigFile::parse(text, false, Some("slow")).is_err(), true);
+
+        let yaml = "worker: 500\nprofiles:\n  stealth:\n    worker: 20\n    queue-size: 100\n";
+        let c = ConfigFile::parse(yaml, true, Some("stealth")).unwrap();
+        assert_eq!(c.value("worker"), Some("20".to_string()));
+        assert_eq!(c.value("queue-size"), Some("100".to_string()));
+        assert_eq!(ConfigFile::parse(yaml, true, None).unwrap().value("worker"), Some("500".to_string()));
+    }
+}
@@ -0,0 +1,81 @@
+use std::io;
+
+use crate::dict::Dict;
+use crate::gen_handler::split_target;
+
+/// Promotes labels that turn out to have children into the depth dictionary.
+///
+/// A name such as `x.api.example.com` shows that `api.example.com` has a
+/// subtree, so `api` is added to the depth set for the rest of the scan and
+/// the grown dictionary is written to `output` at the end.
+#[derive(Clone, Debug)]
+pub struct DepthGrower {
+    targets: Vec<String>,
+    output: String,
+    promoted: Vec<String>,
+}
+
+impl DepthGrower {
+    pub fn new(targets: &[String], output: String) -> Self {
+        Self {
+            targets: targets.to_vec(),
+            output,
+            promoted: Vec::new(),
+        }
+    }
+
+    /// Parent domains of `name` below its target zone as `(domain, label, depth)`,
+    /// nearest parent first.
+    pub fn parents(&self, name: &str) -> Vec<(String, String, usize)> {
+        let (labels, zone) = match split_target(name, &self.targets) {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+
+        let mut parents = Vec::new();
+        for i in 1..labels.len() {
+            let domain = labels[i..].join(".") + "." + zone.as_str() + ".";
+            parents.push((domain, labels[i].to_owned(), labels.len() - i));
+        }
+        parents
+    }
+
+    /// Add `sub` to the depth dictionary, returning false if it was already there.
+    pub fn promote(&mut self, sub: &str, depth_dict: &mut Dict) -> bool {
+        if !depth_dict.insert(sub.to_string()) {
+            return false
+        }
+        info!("Promote {} into the depth dict", sub);
+        self.promoted.push(sub.to_string());
+        true
+    }
+
+    pub fn promoted(&self) -> &Vec<String> {
+        &self.promoted
+    }
+
+    pub fn save(&self, depth_dict: &Dict) -> io::Result<()> {
+        depth_dict.save(self.output.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parents() {
+        let g = DepthGrower::new(&["example.com.".to_string()], String::new());
+        assert_eq!(g.parents("x.eu.api.example.com"), vec![
+            ("eu.api.example.com.".to_string(), "eu".to_string(), 2),
+            ("api.example.com.".to_string(), "api".to_string(), 1),
+        ]);
+        assert_eq!(g.parents("api.example.com."), vec![]);
+
+        let mut d = Dict::from_vec(vec!["dev".to_string()]);
+        let mut g = g;
+        assert_eq!(g.promote("api", &mut d), true);
+        assert_eq!(g.promote("dev", &mut d), false);
+        assert_eq!(d.is_exist(&"api".to_string()), true);
+    }
+}
@@ -1,22 +1,71 @@
-use std::fs::File;
-use std::io::{BufReader, BufRead};
+use std::fs::{self, File};
+use std::path::Path;
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, BufReader, BufRead, BufWriter, Lines, Write};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::error::Error;
+use crate::idn::to_ascii;
+use crate::pattern::Pattern;
 use crate::query::gen_subdomain;
+use crate::dict_index::{dedupe_index, ranked_index, is_index_file};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Dict {
-    dicts: Vec<String>
+    dicts: Vec<String>,
+    index: HashSet<String>,
 }
 
 impl Dict {
-    pub fn new(dict_file: &str) -> Self {
-        let f = File::open(dict_file).unwrap();
+    /// Load and merge every dictionary file, deduplicating the entries.
+    /// Generator entries are expanded up front.
+    pub fn new<S: AsRef<str>>(dict_files: &[S]) -> Result<Self, Error> {
+        let mut dicts = Vec::new();
+        for dict_file in dict_files {
+            let f = open_dict(dict_file.as_ref()).map_err(|e| Error::io(dict_file.as_ref(), e))?;
+            for entry in read_file(f) {
+                match Pattern::parse(entry.as_str()) {
+                    Some(p) => dicts.extend(p.iter()),
+                    None => {}
+                }
+            }
+        }
+        dicts.sort();
+        dicts.dedup();
+        Ok(Self::from_vec(dicts))
+    }
+
+    pub fn from_vec(dicts: Vec<String>) -> Self {
+        let index = dicts.iter().cloned().collect();
         Self {
-            dicts: read_file(f)
+            dicts,
+            index
         }
     }
 
     pub fn is_exist(&self, item: &String) -> bool {
-        self.dicts.contains(item)
+        self.index.contains(item)
+    }
+
+    /// Add an entry, returning false if it is already present.
+    pub fn insert(&mut self, item: String) -> bool {
+        if !self.index.insert(item.to_owned()) {
+            return false
+        }
+        self.dicts.push(item);
+        true
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        let mut dicts = self.dicts.clone();
+        dicts.sort();
+        let mut w = BufWriter::new(File::create(filename)?);
+        for item in dicts {
+            w.write_all(item.as_bytes())?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()
     }
 
     pub fn get_dict(self) -> Vec<String> {
@@ -28,19 +77,213 @@ impl Dict {
     }
 }
 
-fn read_file(f: File) -> Vec<String> {
+/// A dictionary read lazily from disk. Every `iter()` reopens the files, so memory
+/// use does not depend on the number of entries. Entries are yielded unexpanded,
+/// `len()` counts the candidates they expand to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamDict {
+    files: Vec<String>,
+    paths: Vec<String>,
+    len: usize,
+}
+
+impl StreamDict {
+    /// With `dedupe` the entries are streamed from a sorted, deduplicated
+    /// on-disk index instead of the dictionaries themselves, without it a
+    /// single dictionary is read as it is. Several dictionaries are always
+    /// merged through the index. With `ranked` the index is ordered by the
+    /// weight column, highest first. Fails on the first dictionary that
+    /// cannot be opened.
+    pub fn new(dict_files: &[String], dedupe: bool, ranked: bool) -> Result<Self, Error> {
+        for dict_file in dict_files {
+            open_dict(dict_file).map_err(|e| Error::io(dict_file, e))?;
+        }
+
+        let index = if ranked {
+            Some(ranked_index(dict_files))
+        } else if dedupe || dict_files.len() > 1 {
+            Some(dedupe_index(dict_files))
+        } else {
+            None
+        };
+
+        let paths = match index {
+            Some(Ok(index)) => vec![index],
+            Some(Err(e)) => {
+                warn!("[dict] dedupe index {:?} msg: {:?}, reading the dictionaries without deduplication",
+                      dict_files, e.kind());
+                dict_files.to_vec()
+            }
+            None => dict_files.to_vec(),
+        };
+
+        let mut dict = Self {
+            files: dict_files.to_vec(),
+            paths,
+            len: 0
+        };
+        dict.len = dict.iter()
+            .filter_map(|e| Pattern::parse(e.as_str()))
+            .fold(0usize, |n, p| n.saturating_add(p.len()));
+        Ok(dict)
+    }
+
+    /// Every candidate in dictionary order, expanding generator entries lazily.
+    pub fn candidates(&self) -> impl Iterator<Item = String> {
+        self.iter()
+            .filter_map(|e| Pattern::parse(e.as_str()))
+            .flat_map(|p| p.iter())
+    }
+
+    pub fn iter(&self) -> DictIter {
+        DictIter {
+            paths: self.paths.iter().cloned().collect(),
+            lines: None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The dictionary files as given, not the index read in their place.
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+}
+
+pub struct DictIter {
+    paths: VecDeque<String>,
+    lines: Option<Lines<Box<dyn BufRead>>>,
+}
+
+impl Iterator for DictIter {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if let Some(lines) = self.lines.as_mut() {
+                for line in lines {
+                    match line {
+                        Ok(line) => {
+                            if let Some(line) = normalize_entry(line.as_str()) {
+                                return Some(line)
+                            }
+                        }
+                        Err(e) => {
+                            warn!("[dict] read_file msg: {:?}", e.kind());
+                            if e.kind() != io::ErrorKind::InvalidData {
+                                break
+                            }
+                        }
+                    }
+                }
+            }
+
+            let path = self.paths.pop_front()?;
+            self.lines = match open_dict(path.as_str()) {
+                Ok(f) => Some(f.lines()),
+                Err(e) => {
+                    warn!("[dict] open {} msg: {:?}", path, e.kind());
+                    None
+                }
+            };
+        }
+    }
+}
+
+/// Open a dictionary file, decompressing `.gz` and `.zst` files on the fly.
+pub fn open_dict(dict_file: &str) -> io::Result<Box<dyn BufRead>> {
+    let f = File::open(dict_file)?;
+    if dict_file.ends_with(".gz") {
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(f))))
+    }
+    if dict_file.ends_with(".zst") {
+        return Ok(Box::new(BufReader::new(zstd::stream::read::Decoder::new(f)?)))
+    }
+    Ok(Box::new(BufReader::new(f)))
+}
+
+/// Expand directories into the dictionary files they contain.
+/// Index files, see `is_index_file`, and hidden files are skipped.
+pub fn collect_dict_files<S: AsRef<str>>(paths: &[S]) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        if !Path::new(path).is_dir() {
+            File::open(path)?;
+            files.push(path.to_string());
+            continue
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            let name = entry.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if name.starts_with('.') || is_index_file(name) || entry.is_dir() {
+                continue
+            }
+            entries.push(entry.to_string_lossy().to_string());
+        }
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+/// Lowercase a dictionary line and keep it only when it forms a valid label.
+/// Generator entries are kept when every alternative and mask character, and
+/// the longest expansion, make valid labels, see `Pattern::samples`.
+/// An optional weight column is dropped.
+pub fn normalize_entry(line: &str) -> Option<String> {
+    parse_weighted(line).map(|(entry, _)| entry)
+}
+
+/// Split a `word weight` line (separated by whitespace or a comma) into the
+/// normalized entry and its weight. Lines without a numeric last column have no weight.
+/// Unicode entries are converted to punycode, generator syntax must be ASCII.
+pub fn parse_weighted(line: &str) -> Option<(String, Option<f64>)> {
+    let line = line.trim();
+    let (entry, weight) = match line.rfind(|c: char| c.is_whitespace() || c == ',') {
+        Some(n) => match line[n + 1..].parse::<f64>() {
+            Ok(w) if w.is_finite() => (line[..n].trim(), Some(w)),
+            _ => (line, None),
+        },
+        None => (line, None),
+    };
+
+    let entry = if entry.is_ascii() {
+        entry.to_lowercase()
+    } else if Pattern::parse(entry)?.is_literal() {
+        to_ascii(entry)?
+    } else {
+        return None
+    };
+    let pattern = Pattern::parse(entry.as_str())?;
+    if pattern.is_literal() {
+        return gen_subdomain(entry.as_str(), ".1.com").map(|_| (entry, weight))
+    }
+
+    for sub in pattern.samples() {
+        gen_subdomain(sub.as_str(), ".1.com")?;
+    }
+    Some((entry, weight))
+}
+
+fn read_file(reader: Box<dyn BufRead>) -> Vec<String> {
     let mut dict = Vec::new();
-    let reader = BufReader::new(f);
     for line in reader.lines() {
         match line {
             Ok(line) => {
-                let line = line.trim().to_lowercase();
-                if gen_subdomain(line.as_str(), ".1.com").is_some() {
+                if let Some(line) = normalize_entry(line.as_str()) {
                     dict.push(line)
                 }
             }
             Err(e) => {
-                warn!("[dict] read_file msg: {:?}", e.kind())
+                warn!("[dict] read_file msg: {:?}", e.kind());
+                if e.kind() != io::ErrorKind::InvalidData {
+                    break
+                }
             }
         }
     }
@@ -57,9 +300,19 @@ mod test {
 
     #[test]
     fn test_is_exist() {
-        let mut d = Dict::new("depth.txt");
+        let mut d = Dict::new(&["depth.txt"]).unwrap();
         assert_eq!(d.is_exist(&"www".to_string()), false);
         assert_eq!(d.is_exist(&"api".to_string()), true);
         assert_eq!(d.is_exist(&"search".to_string()), false);
     }
+
+    #[test]
+    fn test_stream_dict_missing() {
+        let dir = crate::test_util::TempDir::new();
+        let dict = dir.file("dict.txt", "api\n");
+        let missing = dir.path("missing.txt");
+        assert_eq!(StreamDict::new(&[dict.to_owned()], false, false).is_ok(), true);
+        let r = StreamDict::new(&[dict, missing.to_owned()], true, false);
+        assert_eq!(matches!(r, Err(Error::Io(ref path, _)) if *path == missing), true);
+    }
 }
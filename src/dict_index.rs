@@ -0,0 +1,470 @@
+use std::cmp::{Ordering, Reverse};
+use std::env::{self, temp_dir};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::process;
+use std::collections::BinaryHeap;
+use std::collections::hash_map::DefaultHasher;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Seek, SeekFrom, Write};
+
+use crate::dict::{parse_weighted, open_dict};
+
+// Number of entries sorted in memory before spilling a run to disk.
+const CHUNK_LINES: usize = 1_000_000;
+
+// Entries of a `SortedIndex` block, one key per block is kept in memory.
+const BLOCK_LINES: usize = 1024;
+
+// Part of the index key, bumped whenever the layout of an index changes so
+// indexes built by an older version are not reused.
+const INDEX_VERSION: u32 = 2;
+
+// Tells apart the sorts of one process, the pid those of different ones.
+static SORTS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(test)]
+thread_local! {
+    // set through `test_util::TempDir::cache`
+    pub(crate) static TEST_CACHE: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// An index line: a normalized entry and its optional weight.
+trait Record: Ord + Sized {
+    fn new(entry: String, weight: Option<f64>) -> Self;
+    fn parts(&self) -> (&String, Option<f64>);
+
+    /// Fold a record comparing equal into `self`.
+    fn merge(&mut self, _other: Self) {}
+
+    fn parse(line: &str) -> Option<Self> {
+        parse_weighted(line).map(|(entry, weight)| Self::new(entry, weight))
+    }
+
+    fn line(&self) -> String {
+        match self.parts() {
+            (entry, Some(weight)) => format!("{}\t{}", entry, weight),
+            (entry, None) => entry.to_owned(),
+        }
+    }
+}
+
+// Ordered by entry only, duplicates keep the highest weight.
+struct ByEntry(String, Option<f64>);
+
+impl Record for ByEntry {
+    fn new(entry: String, weight: Option<f64>) -> Self {
+        ByEntry(entry, weight)
+    }
+
+    fn parts(&self) -> (&String, Option<f64>) {
+        (&self.0, self.1)
+    }
+
+    fn merge(&mut self, other: Self) {
+        if cmp_weight(other.1, self.1) == Ordering::Greater {
+            self.1 = other.1;
+        }
+    }
+}
+
+impl PartialEq for ByEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for ByEntry {}
+
+impl PartialOrd for ByEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ByEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+// Ordered by descending weight, then by entry. Unweighted entries come last.
+struct ByWeight(String, Option<f64>);
+
+impl Record for ByWeight {
+    fn new(entry: String, weight: Option<f64>) -> Self {
+        ByWeight(entry, weight)
+    }
+
+    fn parts(&self) -> (&String, Option<f64>) {
+        (&self.0, self.1)
+    }
+}
+
+impl PartialEq for ByWeight {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ByWeight {}
+
+impl PartialOrd for ByWeight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ByWeight {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_weight(other.1, self.1).then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+fn cmp_weight(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Greater,
+        (None, Some(_)) => Ordering::Less,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Merge `dict_files` into one sorted and deduplicated plaintext index and return its path.
+/// Indexes are kept in the cache directory, see `cache_dir`, keyed by the path, size and
+/// modification time of the dictionaries, so a changed dictionary gets a new index.
+/// Memory use is bounded by `CHUNK_LINES`.
+pub fn dedupe_index(dict_files: &[String]) -> io::Result<String> {
+    let index = index_path(dict_files, "idx")?;
+    if Path::new(index.as_str()).exists() {
+        debug!("[dict_index] reuse {}", index);
+        return Ok(index)
+    }
+
+    let mut sorter: Sorter<ByEntry> = Sorter::new(index.as_str());
+    for dict_file in dict_files {
+        for line in open_dict(dict_file.as_str())?.lines() {
+            match line {
+                Ok(line) => sorter.push(line.as_str())?,
+                Err(e) => {
+                    warn!("[dict_index] read {} msg: {:?}", dict_file, e.kind());
+                    if e.kind() != io::ErrorKind::InvalidData {
+                        break
+                    }
+                }
+            }
+        }
+    }
+    sorter.finish()?;
+
+    Ok(index)
+}
+
+/// Like `dedupe_index`, but ordered by descending weight so the most likely
+/// entries are generated first.
+pub fn ranked_index(dict_files: &[String]) -> io::Result<String> {
+    let index = index_path(dict_files, "rank")?;
+    if Path::new(index.as_str()).exists() {
+        debug!("[dict_index] reuse {}", index);
+        return Ok(index)
+    }
+
+    let deduped = dedupe_index(dict_files)?;
+    let mut sorter: Sorter<ByWeight> = Sorter::new(index.as_str());
+    for line in BufReader::new(File::open(deduped)?).lines() {
+        sorter.push(line?.as_str())?;
+    }
+    sorter.finish()?;
+
+    Ok(index)
+}
+
+/// Membership tests on an index written by `dedupe_index` without loading it.
+/// The first entry of every block of `BLOCK_LINES` is kept with its offset,
+/// a lookup seeks to the only block that may hold the entry and scans it.
+#[derive(Debug)]
+pub struct SortedIndex {
+    keys: Vec<(String, u64)>,
+    file: Mutex<BufReader<File>>,
+}
+
+impl SortedIndex {
+    pub fn open(index: &str) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(index)?);
+        let mut keys = Vec::new();
+        let mut line = String::new();
+        let mut offset = 0u64;
+        for n in 0.. {
+            line.clear();
+            let read = r.read_line(&mut line)?;
+            if read == 0 {
+                break
+            }
+            if n % BLOCK_LINES == 0 {
+                keys.push((index_entry(line.as_str()).to_string(), offset));
+            }
+            offset += read as u64;
+        }
+
+        Ok(Self {
+            keys,
+            file: Mutex::new(r),
+        })
+    }
+
+    pub fn contains(&self, entry: &str) -> io::Result<bool> {
+        let block = self.keys.partition_point(|(k, _)| k.as_str() <= entry);
+        if block == 0 {
+            return Ok(false)
+        }
+
+        let mut r = match self.file.lock() {
+            Ok(r) => r,
+            Err(e) => e.into_inner(),
+        };
+        r.seek(SeekFrom::Start(self.keys[block - 1].1))?;
+        let mut line = String::new();
+        for _ in 0..BLOCK_LINES {
+            line.clear();
+            if r.read_line(&mut line)? == 0 {
+                break
+            }
+            match index_entry(line.as_str()).cmp(entry) {
+                Ordering::Less => {}
+                Ordering::Equal => return Ok(true),
+                Ordering::Greater => break,
+            }
+        }
+        Ok(false)
+    }
+}
+
+// The entry of an index line, without its weight.
+fn index_entry(line: &str) -> &str {
+    let line = line.trim_end_matches(['\n', '\r']);
+    line.split('\t').next().unwrap_or(line)
+}
+
+/// Whether `name` was written by the index writer: an index, a run spilled
+/// while sorting or an unfinished index. Older versions kept them next to the dictionaries.
+pub fn is_index_file(name: &str) -> bool {
+    let mut base = name;
+    while let Some(n) = base.rfind('.') {
+        let ext = &base[n + 1..];
+        if ext != "tmp" && (ext.is_empty() || !ext.chars().all(|c| c.is_ascii_digit() || c == '-')) {
+            break
+        }
+        base = &base[..n];
+    }
+    base.ends_with(".idx") || base.ends_with(".rank")
+}
+
+fn index_path(dict_files: &[String], extension: &str) -> io::Result<String> {
+    let mut hasher = DefaultHasher::new();
+    INDEX_VERSION.hash(&mut hasher);
+    for f in dict_files {
+        let path = fs::canonicalize(f)?;
+        let meta = fs::metadata(&path)?;
+        path.hash(&mut hasher);
+        meta.len().hash(&mut hasher);
+        meta.modified()?.hash(&mut hasher);
+    }
+    let name = format!("{:016x}.{}", hasher.finish(), extension);
+    Ok(cache_dir().join(name).to_string_lossy().to_string())
+}
+
+/// `$XDG_CACHE_HOME/baddns` or `~/.cache/baddns`, falling back to the temp
+/// directory. Dictionary directories are often shared and read-only.
+pub fn cache_dir() -> PathBuf {
+    #[cfg(test)]
+    {
+        if let Some(dir) = TEST_CACHE.with(|c| c.borrow().clone()) {
+            return dir
+        }
+    }
+    let base = match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+        (Some(d), _) if !d.is_empty() => PathBuf::from(d),
+        (_, Some(h)) if !h.is_empty() => PathBuf::from(h).join(".cache"),
+        _ => return temp_dir(),
+    };
+    let dir = base.join("baddns");
+    match fs::create_dir_all(&dir) {
+        Ok(_) => dir,
+        Err(e) => {
+            warn!("[dict_index] cache {:?} msg: {:?}, using the temp directory", dir, e.kind());
+            temp_dir()
+        }
+    }
+}
+
+// External merge sort: sorted runs of `CHUNK_LINES` records are spilled next to
+// the index and merged into it, folding records that compare equal. The runs
+// and the unfinished index are named after the sort, so scans building the
+// same index at once do not share files, and the index appears by a rename.
+struct Sorter<T: Record> {
+    index: String,
+    // `<index>.<pid>-<n>`, the runs add `.<chunk>` and the unfinished index `.tmp`
+    work: String,
+    chunks: Vec<String>,
+    buf: Vec<T>,
+}
+
+impl<T: Record> Sorter<T> {
+    fn new(index: &str) -> Self {
+        Self {
+            index: index.to_string(),
+            work: format!("{}.{}-{}", index, process::id(), SORTS.fetch_add(1, AtomicOrdering::SeqCst)),
+            chunks: Vec::new(),
+            buf: Vec::with_capacity(CHUNK_LINES),
+        }
+    }
+
+    fn push(&mut self, line: &str) -> io::Result<()> {
+        if let Some(record) = T::parse(line) {
+            self.buf.push(record);
+        }
+        if self.buf.len() >= CHUNK_LINES {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.buf.sort();
+
+        let name = format!("{}.{}", self.work, self.chunks.len());
+        let mut w = BufWriter::new(File::create(name.as_str())?);
+        write_folded(&mut w, self.buf.drain(..))?;
+        w.flush()?;
+        self.chunks.push(name);
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if !self.buf.is_empty() || self.chunks.is_empty() {
+            self.spill()?;
+        }
+
+        let tmp = self.work.to_owned() + ".tmp";
+        let mut readers: Vec<Lines<BufReader<File>>> = Vec::new();
+        for chunk in self.chunks.iter() {
+            readers.push(BufReader::new(File::open(chunk)?).lines());
+        }
+
+        let mut heap = BinaryHeap::new();
+        for (n, r) in readers.iter_mut().enumerate() {
+            if let Some(record) = next_record::<T>(r)? {
+                heap.push(Reverse((record, n)));
+            }
+        }
+
+        let mut w = BufWriter::new(File::create(tmp.as_str())?);
+        let merged = std::iter::from_fn(|| {
+            let Reverse((record, n)) = heap.pop()?;
+            match next_record::<T>(&mut readers[n]) {
+                Ok(Some(next)) => heap.push(Reverse((next, n))),
+                Ok(None) => {}
+                Err(e) => warn!("[dict_index] read {} msg: {:?}", self.chunks[n], e.kind()),
+            }
+            Some(record)
+        });
+        write_folded(&mut w, merged)?;
+        w.flush()?;
+
+        for chunk in self.chunks.iter() {
+            let _ = fs::remove_file(chunk);
+        }
+        fs::rename(tmp, self.index.as_str())
+    }
+}
+
+fn next_record<T: Record>(lines: &mut Lines<BufReader<File>>) -> io::Result<Option<T>> {
+    for line in lines {
+        if let Some(record) = T::parse(line?.as_str()) {
+            return Ok(Some(record))
+        }
+    }
+    Ok(None)
+}
+
+// Write sorted records, folding neighbours that compare equal.
+fn write_folded<T: Record, W: Write, I: Iterator<Item = T>>(w: &mut W, records: I) -> io::Result<()> {
+    let mut last: Option<T> = None;
+    for record in records {
+        match last.as_mut() {
+            Some(l) if *l == record => {
+                l.merge(record);
+                continue
+            }
+            _ => {}
+        }
+        if let Some(l) = last.replace(record) {
+            w.write_all(l.line().as_bytes())?;
+            w.write_all(b"\n")?;
+        }
+    }
+    if let Some(l) = last {
+        w.write_all(l.line().as_bytes())?;
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_dedupe_index() {
+        let dir = TempDir::new();
+        dir.cache();
+        let dict = dir.file("dict.txt", "www\nAPI\nmail\napi\n\nwww\n");
+
+        let index = dedupe_index(&[dict.to_string()]).unwrap();
+        assert_eq!(index.starts_with(cache_dir().to_str().unwrap()), true);
+        assert_eq!(fs::read_to_string(index.as_str()).unwrap(), "api\nmail\nwww\n");
+        // the runs and the unfinished index are gone
+        assert_eq!(fs::read_dir(cache_dir()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_sorted_index() {
+        let dir = TempDir::new();
+        dir.cache();
+        let words: Vec<String> = (0..3000).map(|n| format!("w{}\t{}", n * 2, n)).collect();
+        let dict = dir.file("dict.txt", words.join("\n").as_str());
+
+        let index = SortedIndex::open(dedupe_index(&[dict.to_string()]).unwrap().as_str()).unwrap();
+        assert_eq!(index.keys.len(), 3);
+        for n in [0, 2, 1000, 1998, 4000, 5998] {
+            assert_eq!(index.contains(format!("w{}", n).as_str()).unwrap(), true);
+            assert_eq!(index.contains(format!("w{}", n + 1).as_str()).unwrap(), false);
+        }
+        assert_eq!(index.contains("a").unwrap(), false);
+        assert_eq!(index.contains("z").unwrap(), false);
+    }
+
+    #[test]
+    fn test_is_index_file() {
+        assert_eq!(is_index_file("dict.txt.idx"), true);
+        assert_eq!(is_index_file("dict.txt.rank.tmp"), true);
+        assert_eq!(is_index_file("dict.txt.idx.3"), true);
+        assert_eq!(is_index_file("0123abcd.idx.4242-1.tmp"), true);
+        assert_eq!(is_index_file("0123abcd.rank.4242-1.0"), true);
+        assert_eq!(is_index_file("dict.txt"), false);
+        assert_eq!(is_index_file("dict.2"), false);
+    }
+
+    #[test]
+    fn test_ranked_index() {
+        let dir = TempDir::new();
+        dir.cache();
+        let dict = dir.file("dict.txt", "mail 10\nwww\t500\napi,20\nmail 40\ntest\n");
+
+        let index = ranked_index(&[dict.to_string()]).unwrap();
+        assert_eq!(fs::read_to_string(index.as_str()).unwrap(), "www\t500\nmail\t40\napi\t20\ntest\n");
+    }
+}
@@ -0,0 +1,188 @@
+use crate::dict::StreamDict;
+use crate::dict_index::{dedupe_index, SortedIndex};
+
+/// Which queries a dictionary rule applies to: `[TARGET][@DEPTH]`, where
+/// DEPTH is `N` or `N+` and TARGET is a domain or `*.domain`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Selector {
+    target: Option<String>,
+    depth: Option<(usize, bool)>,
+}
+
+impl Selector {
+    pub fn parse(s: &str) -> Option<Selector> {
+        let s = s.trim().to_lowercase();
+        let (target, depth) = match s.rfind('@') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            // a bare selector is a depth if it looks like one
+            None if parse_depth(s.as_str()).is_some() => ("", Some(s.as_str())),
+            None => (s.as_str(), None),
+        };
+
+        let depth = match depth {
+            Some(d) => Some(parse_depth(d)?),
+            None => None,
+        };
+        let target = target.trim_end_matches('.');
+        let target = if target.is_empty() {
+            None
+        } else if target.trim_start_matches("*.").contains('*') {
+            return None
+        } else {
+            Some(target.to_string())
+        };
+
+        if target.is_none() && depth.is_none() {
+            return None
+        }
+        Some(Selector { target, depth })
+    }
+
+    pub fn matches(&self, domain: &str, depth: usize) -> bool {
+        if let Some((n, plus)) = self.depth {
+            if depth < n || (!plus && depth != n) {
+                return false
+            }
+        }
+
+        let domain = domain.trim_end_matches('.');
+        match self.target.as_ref() {
+            Some(t) if t.starts_with("*.") => domain.ends_with(&t[1..]),
+            Some(t) => domain == t || domain.ends_with(&format!(".{}", t)),
+            None => true,
+        }
+    }
+}
+
+fn parse_depth(s: &str) -> Option<(usize, bool)> {
+    let plus = s.ends_with('+');
+    let n = s.trim_end_matches('+').parse().ok()?;
+    Some((n, plus))
+}
+
+/// Split a `SELECTOR=FILE[,FILE]` rule.
+pub fn parse_rule(rule: &str) -> Option<(Selector, Vec<String>)> {
+    let mut parts = rule.splitn(2, '=');
+    let selector = Selector::parse(parts.next()?)?;
+    let files: Vec<String> = parts.next()?
+        .split(',')
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect();
+    if files.is_empty() {
+        return None
+    }
+    Some((selector, files))
+}
+
+/// The dictionaries used for brute forcing, picked per target and per depth.
+/// The first matching rule wins, everything else uses the default dictionary.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DictSet {
+    default: StreamDict,
+    rules: Vec<(Selector, StreamDict)>,
+}
+
+impl DictSet {
+    pub fn new(default: StreamDict) -> Self {
+        Self {
+            default,
+            rules: Vec::new()
+        }
+    }
+
+    pub fn add_rule(&mut self, selector: Selector, dict: StreamDict) {
+        self.rules.push((selector, dict))
+    }
+
+    /// The dictionary used to expand `domain` at `depth`.
+    pub fn select(&self, domain: &str, depth: usize) -> &StreamDict {
+        self.rules.iter()
+            .find(|(s, _)| s.matches(domain, depth))
+            .map(|(_, d)| d)
+            .unwrap_or(&self.default)
+    }
+
+    pub fn get_default(&self) -> &StreamDict {
+        &self.default
+    }
+
+    pub fn rules_len(&self) -> usize {
+        self.rules.len()
+    }
+}
+
+/// The entries of the dictionaries of a `DictSet`, to tell whether brute
+/// forcing already queries a name. Lookups go to the sorted dedupe index of
+/// every dictionary on disk, the entries are not loaded.
+#[derive(Debug, Default)]
+pub struct DictWords {
+    default: Option<SortedIndex>,
+    rules: Vec<(Selector, Option<SortedIndex>)>,
+}
+
+impl DictWords {
+    pub fn new(dicts: &DictSet) -> Self {
+        Self {
+            default: open_words(&dicts.default),
+            rules: dicts.rules.iter().map(|(s, d)| (s.clone(), open_words(d))).collect(),
+        }
+    }
+
+    /// Whether expanding `domain` at `depth` queries `word`.
+    pub fn contains(&self, domain: &str, depth: usize, word: &str) -> bool {
+        let words = self.rules.iter()
+            .find(|(s, _)| s.matches(domain, depth))
+            .map(|(_, w)| w)
+            .unwrap_or(&self.default);
+        match words.as_ref().map(|w| w.contains(word)) {
+            Some(Ok(found)) => found,
+            Some(Err(e)) => {
+                warn!("[dict_set] lookup {} msg: {:?}", word, e.kind());
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+// Generator entries never equal a word, they need not be skipped.
+fn open_words(dict: &StreamDict) -> Option<SortedIndex> {
+    if dict.files().is_empty() {
+        return None
+    }
+    match dedupe_index(dict.files()).and_then(|index| SortedIndex::open(index.as_str())) {
+        Ok(index) => Some(index),
+        Err(e) => {
+            warn!("[dict_set] index {:?} msg: {:?}, names already in the dictionaries are queried again",
+                  dict.files(), e.kind());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_selector() {
+        let s = Selector::parse("2+").unwrap();
+        assert_eq!(s.matches("api.example.com.", 1), false);
+        assert_eq!(s.matches("dev.api.example.com.", 3), true);
+
+        let s = Selector::parse("*.corp.example.com@2").unwrap();
+        assert_eq!(s.matches("a.corp.example.com.", 2), true);
+        assert_eq!(s.matches("corp.example.com.", 2), false);
+        assert_eq!(s.matches("a.corp.example.com.", 3), false);
+
+        let s = Selector::parse("Example.com").unwrap();
+        assert_eq!(s.matches("example.com.", 1), true);
+        assert_eq!(s.matches("badexample.com.", 1), false);
+
+        assert_eq!(Selector::parse("example.com@x"), None);
+        assert_eq!(Selector::parse("a*.example.com"), None);
+        assert_eq!(parse_rule("2+="), None);
+        assert_eq!(parse_rule("1=big.txt,extra.txt").unwrap().1, vec!["big.txt", "extra.txt"]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+use crate::dict::{open_dict, parse_weighted};
+use crate::dict_index::dedupe_index;
+use crate::pattern::Pattern;
+
+// Longest label allowed by RFC 1035.
+const MAX_LABEL: usize = 63;
+
+/// Why a dictionary line was dropped.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Invalid {
+    Idn,
+    BadPattern,
+    LabelTooLong(usize),
+    BadCharacter(char),
+    Rejected,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Invalid::Idn => write!(f, "invalid internationalised name"),
+            Invalid::BadPattern => write!(f, "malformed generator syntax"),
+            Invalid::LabelTooLong(n) => write!(f, "label too long ({} > {})", n, MAX_LABEL),
+            Invalid::BadCharacter(c) => write!(f, "bad character {:?}", c),
+            Invalid::Rejected => write!(f, "not a valid DNS name"),
+        }
+    }
+}
+
+/// Validate one dictionary line the way the scanner reads it. Blank lines
+/// are `Ok(None)`, valid lines yield the normalized entry and its weight.
+pub fn check_entry(line: &str) -> Result<Option<(String, Option<f64>)>, Invalid> {
+    if line.trim().is_empty() {
+        return Ok(None)
+    }
+    if let Some(entry) = parse_weighted(line) {
+        return Ok(Some(entry))
+    }
+    if !line.is_ascii() {
+        return Err(Invalid::Idn)
+    }
+
+    // diagnose the entry without its weight column, commas also separate alternatives
+    let line = line.trim();
+    let entry = match line.rfind(|c: char| c.is_whitespace() || c == ',') {
+        Some(n) if line[n + 1..].parse::<f64>().is_ok() => line[..n].trim(),
+        _ => line,
+    };
+    let entry = entry.to_lowercase();
+    let pattern = Pattern::parse(entry.as_str()).ok_or(Invalid::BadPattern)?;
+    for sub in pattern.samples() {
+        if let Some(l) = sub.split('.').map(|l| l.len()).find(|l| *l > MAX_LABEL) {
+            return Err(Invalid::LabelTooLong(l))
+        }
+        if let Some(c) = sub.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == '.')) {
+            return Err(Invalid::BadCharacter(c))
+        }
+    }
+    Err(Invalid::Rejected)
+}
+
+/// Summary of a dictionary run through `clean`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DictStats {
+    pub lines: usize,
+    pub invalid: usize,
+    pub duplicates: usize,
+    pub entries: usize,
+    pub candidates: usize,
+    pub weighted: usize,
+    // label length -> number of entries, measured on the first expansion
+    pub label_lengths: BTreeMap<usize, usize>,
+    // labels per entry -> number of entries
+    pub label_counts: BTreeMap<usize, usize>,
+}
+
+impl fmt::Display for DictStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "lines:      {}", self.lines)?;
+        writeln!(f, "invalid:    {}", self.invalid)?;
+        writeln!(f, "duplicates: {}", self.duplicates)?;
+        writeln!(f, "entries:    {}", self.entries)?;
+        writeln!(f, "candidates: {}", self.candidates)?;
+        writeln!(f, "weighted:   {}", self.weighted)?;
+        writeln!(f, "label length distribution:")?;
+        for (len, n) in self.label_lengths.iter() {
+            writeln!(f, "  {:>3} {}", len, n)?;
+        }
+        writeln!(f, "labels per entry:")?;
+        for (len, n) in self.label_counts.iter() {
+            writeln!(f, "  {:>3} {}", len, n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Validate, merge and deduplicate `dict_files` into `output`, calling
+/// `report` for every invalid line with its file, line number and reason.
+/// Duplicates keep their highest weight, the result is sorted.
+pub fn clean<F>(dict_files: &[String], output: &str, mut report: F) -> io::Result<DictStats>
+    where F: FnMut(&str, usize, &str, &Invalid) {
+    let mut stats = DictStats::default();
+    let valid = output.to_owned() + ".valid";
+
+    {
+        let mut w = BufWriter::new(File::create(valid.as_str())?);
+        for dict_file in dict_files {
+            for (n, line) in open_dict(dict_file.as_str())?.lines().enumerate() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        warn!("[dict_tool] read {} msg: {:?}", dict_file, e.kind());
+                        if e.kind() != io::ErrorKind::InvalidData {
+                            break
+                        }
+                        continue
+                    }
+                };
+                stats.lines += 1;
+                match check_entry(line.as_str()) {
+                    Ok(Some(_)) => {
+                        stats.entries += 1;
+                        w.write_all(line.trim().as_bytes())?;
+                        w.write_all(b"\n")?;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        stats.invalid += 1;
+                        report(dict_file.as_str(), n + 1, line.as_str(), &e);
+                    }
+                }
+            }
+        }
+        w.flush()?;
+    }
+
+    let index = dedupe_index(&[valid.to_owned()])?;
+    // the index lives in the cache directory, possibly on another file system
+    if fs::rename(index.as_str(), output).is_err() {
+        fs::copy(index.as_str(), output)?;
+        let _ = fs::remove_file(index.as_str());
+    }
+    let _ = fs::remove_file(valid);
+
+    let valid_entries = stats.entries;
+    stats.entries = 0;
+    for line in BufReader::new(File::open(output)?).lines() {
+        let (entry, weight) = match parse_weighted(line?.as_str()) {
+            Some(e) => e,
+            None => continue,
+        };
+        let pattern = match Pattern::parse(entry.as_str()) {
+            Some(p) => p,
+            None => continue,
+        };
+        stats.entries += 1;
+        stats.candidates = stats.candidates.saturating_add(pattern.len());
+        if weight.is_some() {
+            stats.weighted += 1;
+        }
+        if let Some(first) = pattern.first() {
+            for label in first.split('.') {
+                *stats.label_lengths.entry(label.len()).or_insert(0) += 1;
+            }
+            *stats.label_counts.entry(first.split('.').count()).or_insert(0) += 1;
+        }
+    }
+    stats.duplicates = valid_entries - stats.entries;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_check_entry() {
+        assert_eq!(check_entry("WWW 10"), Ok(Some(("www".to_string(), Some(10.0)))));
+        assert_eq!(check_entry("   "), Ok(None));
+        assert_eq!(check_entry("测试"), Ok(Some(("xn--0zwm56d".to_string(), None))));
+        assert_eq!(check_entry("a\u{FFFD}b"), Err(Invalid::Idn));
+        assert_eq!(check_entry(&"a".repeat(64)), Err(Invalid::LabelTooLong(64)));
+        assert_eq!(check_entry("api{1-"), Err(Invalid::BadPattern));
+        assert_eq!(check_entry("{a,b!c,d}"), Err(Invalid::BadCharacter('!')));
+        assert_eq!(check_entry(&format!("{{a,{},b}}", "c".repeat(64))), Err(Invalid::LabelTooLong(64)));
+    }
+
+    #[test]
+    fn test_clean() {
+        let dir = TempDir::new();
+        let dict = dir.file("dict.txt", "www\nAPI 5\nmail\napi 9\na\u{FFFD}b\napi{1-3}\n\nwww\n");
+        let output = dir.path("dict.out");
+
+        let mut invalid = Vec::new();
+        let stats = clean(&[dict.to_owned()], output.as_str(), |_, n, _, e| invalid.push((n, e.clone()))).unwrap();
+        assert_eq!(fs::read_to_string(output.as_str()).unwrap(), "api\t9\napi{1-3}\nmail\nwww\n");
+        assert_eq!(invalid, vec![(5, Invalid::Idn)]);
+        assert_eq!(stats.entries, 4);
+        assert_eq!(stats.duplicates, 2);
+        assert_eq!(stats.candidates, 6);
+    }
+}
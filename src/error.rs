@@ -0,0 +1,48 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by the library, the binary decides how to report them.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the file failed.
+    Io(String, io::Error),
+    /// Encoding a result failed.
+    Json(serde_json::Error),
+    /// An option or the configuration file is invalid.
+    Config(String),
+    /// A pipeline thread panicked, the scan is incomplete.
+    Panicked(&'static str),
+}
+
+impl Error {
+    pub fn io(path: &str, e: io::Error) -> Self {
+        Error::Io(path.to_owned(), e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{} {}", path, e),
+            Error::Json(e) => write!(f, "encode result {}", e),
+            Error::Config(e) => write!(f, "config {}", e),
+            Error::Panicked(name) => write!(f, "{} thread panicked", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Config(_) | Error::Panicked(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
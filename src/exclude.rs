@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::net::IpAddr;
+use std::io::{self, BufRead, BufReader};
+
+use ipnet::IpNet;
+
+use crate::idn::to_ascii;
+
+/// What `check_event` does with results resolving into an excluded range.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExcludeMode {
+    Drop,
+    Flag,
+}
+
+/// Out-of-scope names and address ranges. A name line excludes the domain
+/// and everything below it, `*.domain` only what is below it. Addresses are
+/// given as CIDR ranges or single IPs.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Exclusions {
+    // (suffix without trailing dot, whether the suffix itself is excluded)
+    suffixes: Vec<(String, bool)>,
+    nets: Vec<IpNet>,
+}
+
+impl Exclusions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(filename: &str) -> io::Result<Self> {
+        let mut e = Self::new();
+        for (n, line) in BufReader::new(File::open(filename)?).lines().enumerate() {
+            let line = line?;
+            if !e.add(line.as_str()) {
+                warn!("[exclude] {}:{} invalid entry: {:?}", filename, n + 1, line);
+            }
+        }
+        Ok(e)
+    }
+
+    /// Add one entry, returning false if it is neither a name nor a range.
+    /// Blank lines and `#` comments are accepted and ignored.
+    pub fn add(&mut self, line: &str) -> bool {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            return true
+        }
+        if let Ok(net) = line.parse::<IpNet>() {
+            self.nets.push(net);
+            return true
+        }
+        if let Ok(ip) = line.parse::<IpAddr>() {
+            self.nets.push(IpNet::from(ip));
+            return true
+        }
+
+        let (name, itself) = match line.strip_prefix("*.") {
+            Some(name) => (name, false),
+            None => (line.trim_start_matches('.'), true),
+        };
+        match to_ascii(name.trim_end_matches('.')) {
+            Some(name) if is_name(name.as_str()) => {
+                self.suffixes.push((name, itself));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.suffixes.is_empty() && self.nets.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.suffixes.len() + self.nets.len()
+    }
+
+    /// Whether querying `name` is out of scope.
+    pub fn is_excluded(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.');
+        self.suffixes.iter().any(|(s, itself)| {
+            (*itself && name == s) || is_below(name, s)
+        })
+    }
+
+    /// Whether every name below `domain` is out of scope, so its subtree must not be expanded.
+    pub fn covers(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        self.suffixes.iter().any(|(s, _)| domain == s || is_below(domain, s))
+    }
+
+    /// Whether any resolved address in `collect` falls into an excluded range.
+    pub fn is_excluded_collect(&self, collect: &[String]) -> bool {
+        if self.nets.is_empty() {
+            return false
+        }
+        collect.iter()
+            .filter_map(|r| r.parse::<IpAddr>().ok())
+            .any(|ip| self.nets.iter().any(|n| n.contains(&ip)))
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.split('.').all(|l| {
+        !l.is_empty() && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+fn is_below(name: &str, suffix: &str) -> bool {
+    name.len() > suffix.len() + 1
+        && name.ends_with(suffix)
+        && name.as_bytes()[name.len() - suffix.len() - 1] == b'.'
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exclusions() {
+        let mut e = Exclusions::new();
+        for line in vec!["secret.example.com", "*.corp.example.com", "10.0.0.0/8", "192.168.1.1 # gateway", "# scope"] {
+            assert_eq!(e.add(line), true);
+        }
+        assert_eq!(e.add("not a name/"), false);
+        assert_eq!(e.len(), 4);
+
+        assert_eq!(e.is_excluded("secret.example.com."), true);
+        assert_eq!(e.is_excluded("a.secret.example.com."), true);
+        assert_eq!(e.is_excluded("notsecret.example.com."), false);
+        assert_eq!(e.is_excluded("corp.example.com."), false);
+        assert_eq!(e.is_excluded("www.corp.example.com."), true);
+        assert_eq!(e.covers("corp.example.com."), true);
+        assert_eq!(e.covers("example.com."), false);
+
+        assert_eq!(e.is_excluded_collect(&["10.1.2.3".to_string()]), true);
+        assert_eq!(e.is_excluded_collect(&["cname.example.net.".to_string(), "192.168.1.2".to_string()]), false);
+    }
+}
@@ -1,10 +1,16 @@
 use std::thread;
-use std::fs::File;
-use std::time::Duration;
-use std::io::{BufReader, BufRead};
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
 
-use crate::mem_util::{mem_total, get_use_mem4pid, Statue, QueueMessage};
+use crate::error::Error;
+use crate::checkpoint::Checkpoint;
+use crate::dict_set::DictSet;
+use crate::target::load_targets;
+use crate::exclude::Exclusions;
+use crate::mem_util::{send_statue, Statue, QueueMessage};
 
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -13,90 +19,206 @@ pub struct Item {
     pub subdomain: String,
     pub depth: usize,
     pub collect: Option<Vec<String>>,
+    pub sample: bool,
+    // position in the expansion it was generated by, see `checkpoint`
+    pub index: Option<usize>,
 }
 
-pub fn gen_event(gen_recv: Receiver<QueueMessage>, query_send: Sender<QueueMessage>,
-                 sub_list: Vec<String>, target: Vec<String>, statistical_send: Sender<Statue>, pid: u32, use_mem:f64) -> thread::JoinHandle<()> {
+/// What the gen stage generates from, built by `scanner::ScanBuilder`.
+pub struct GenOptions {
+    pub dicts: DictSet,
+    pub targets: Vec<String>,
+    pub exclude: Exclusions,
+    // nothing new is generated once it is set
+    pub stop: Arc<AtomicBool>,
+}
+
+/// Generate the queries. `query_send` is bounded, so generation blocks
+/// while the query stage is behind.
+pub fn gen_event(gen_recv: Receiver<QueueMessage>, query_send: SyncSender<QueueMessage>,
+                 options: GenOptions, resume: &Checkpoint,
+                 statistical_send: Sender<Statue>) -> thread::JoinHandle<()> {
+    let GenOptions { dicts, targets: target, exclude, stop } = options;
+    let resumed: HashSet<String> = resume.expanded.iter().cloned().collect();
+    // words learned before the scan was resumed, see `QueueMessage::Learned`
+    let mut learned = resume.learned.clone();
+
+    // registered before the thread starts so the scan cannot look finished
+    // before the targets were queued
+    let targets = target.iter().filter(|t| !resumed.contains(*t)).count();
+    send_statue(&statistical_send, Statue::Expand(String::new(), targets), &stop);
+
     thread::spawn(move || {
         debug!("Start gen for target list ");
-        let statistical_send_target = statistical_send.clone();
+        let stopped = || stop.load(Ordering::SeqCst);
+        // the query thread is only gone if it panicked, the scan is stopped
+        // and this thread ends so the others can wind down
+        let gone = Cell::new(false);
+        let send_query = |q: QueueMessage| -> bool {
+            if query_send.send(q).is_err() {
+                error!("[gen_handler] send query_send, stopping the scan");
+                stop.store(true, Ordering::SeqCst);
+                gone.set(true);
+                return false
+            }
+            true
+        };
 
-        for t in target.iter() {
-            match statistical_send_target.send(Statue::Querys) {
-                Ok(_) => {},
-                Err(_) => {
-                    error!("[gen_handler] gen_event send Statue::Querys ");
-                }
-            };
+        // out of scope names are never queried and nothing new is sent once
+        // the scan is stopping, returns whether `item` was sent
+        let send_job = |item: Item| -> bool {
+            if stopped() || exclude.is_excluded(item.subdomain.as_str()) {
+                return false
+            }
+            send_statue(&statistical_send, Statue::Queued(item.subdomain.to_owned()), &stop);
+            send_query(QueueMessage::Job(item))
+        };
 
-            let item = gen_item(t.as_str(), "", 0);
-            query_send.send(QueueMessage::Job(item)).unwrap();
+        // targets expanded before the scan was resumed are not queried again
+        let mut sent = 0;
+        for t in target.iter().filter(|t| !resumed.contains(*t)) {
+            if stopped() {
+                break
+            }
+            send_statue(&statistical_send, Statue::Queued(t.to_owned()), &stop);
+            if !send_query(QueueMessage::Job(gen_item(t.as_str(), "", 0))) {
+                break
+            }
+            sent += 1;
         }
+        expanded(&statistical_send, "", targets, sent, &stop);
 
-
-        let statistical_send = statistical_send.clone();
+        // words learned from results are appended to every expansion
 
         for q in gen_recv {
             match q {
                 QueueMessage::Gen(item) => {
-                    for sub in &sub_list {
-                        if supper(use_mem, pid) {
-                            thread::sleep(Duration::new(2, 0))
+                    let sub_list = dicts.select(item.domain.as_str(), item.depth);
+                    let expected = sub_list.len().saturating_sub(item.skip) + learned.len();
+                    let mut sent = 0;
+                    // candidates before `skip` were already queried by a sample
+                    // or before the scan was resumed
+                    let mut index = 0;
+                    for sub in sub_list.candidates() {
+                        if stopped() {
+                            break
                         }
-                        statistical_send.send(Statue::Querys).unwrap();
-                        let item = gen_item(item.domain.as_ref(), sub, item.depth);
-                        query_send.send(QueueMessage::Job(item)).unwrap();
+                        let mut job = gen_item(item.domain.as_ref(), sub.as_str(), item.depth);
+                        if exclude.is_excluded(job.subdomain.as_str()) {
+                            continue
+                        }
+                        index += 1;
+                        if index <= item.skip {
+                            continue
+                        }
+                        job.index = Some(index - 1);
+                        if send_job(job) { sent += 1 }
                     };
+
+                    for sub in learned.iter() {
+                        let mut job = gen_item(item.domain.as_ref(), sub.as_str(), item.depth);
+                        if exclude.is_excluded(job.subdomain.as_str()) {
+                            continue
+                        }
+                        index += 1;
+                        if index <= item.skip {
+                            continue
+                        }
+                        job.index = Some(index - 1);
+                        if send_job(job) { sent += 1 }
+                    }
+                    // lets a checkpoint drop the expansion once all of it is checked
+                    if !stopped() {
+                        send_query(QueueMessage::Complete(item.domain.to_owned(), index));
+                    }
+                    expanded(&statistical_send, item.domain.as_str(), expected, sent, &stop);
+                }
+                QueueMessage::Sample(item, size) => {
+                    let sub_list = dicts.select(item.domain.as_str(), item.depth);
+                    let mut sent = 0;
+                    for sub in sub_list.candidates() {
+                        if sent >= size || stopped() {
+                            break
+                        }
+                        let mut job = gen_item(item.domain.as_ref(), sub.as_str(), item.depth);
+                        job.sample = true;
+                        job.index = Some(sent);
+                        if send_job(job) { sent += 1 }
+                    }
+                    // excluded candidates are not replaced, the sample decides on those
+                    // sent. Also sent when stopped, check releases the sample with it
+                    send_query(QueueMessage::Complete(item.domain.to_owned(), sent));
+                    expanded(&statistical_send, item.domain.as_str(), size, sent, &stop);
+                }
+                QueueMessage::Learned(words) => {
+                    let mut sent = 0;
+                    for t in target.iter() {
+                        for sub in words.iter() {
+                            if send_job(gen_item(t.as_str(), sub.as_str(), 1)) { sent += 1 }
+                        }
+                    }
+                    expanded(&statistical_send, "", target.len() * words.len(), sent, &stop);
+                    learned.extend(words);
+                }
+                QueueMessage::Candidates(name, items) => {
+                    let expected = items.len();
+                    let mut sent = 0;
+                    for item in items {
+                        if send_job(item) { sent += 1 }
+                    }
+                    expanded(&statistical_send, name.as_str(), expected, sent, &stop);
                 }
                 QueueMessage::Terminate => {
-                    query_send.send(QueueMessage::Terminate).unwrap();
+                    send_query(QueueMessage::Terminate);
                     break
                 }
                 _ => {}
             }
+            if gone.get() {
+                break
+            }
         }
         debug!("[gen_handler] break end");
     })
 }
 
-pub fn init_target(filename: &str) -> Vec<String> {
-    let mut target = Vec::new();
-
-    let f = File::open(filename).unwrap();
-
-    let reader = BufReader::new(f);
-
-    for item in reader.lines() {
-        match item {
-            Ok(item) => {
-                let item = item.trim().to_lowercase() + ".";
-                target.push(item);
-            }
-            Err(e) => {
-                warn!("[item] init target {:?}", e)
-            }
-        }
-    }
+// Release the expansion below `name` once all its queries are queued,
+// candidates announced but excluded or stopped here are taken back out of the total.
+fn expanded(statistical_send: &Sender<Statue>, name: &str, expected: usize, sent: usize, stop: &AtomicBool) {
+    send_statue(statistical_send, Statue::Expanded(name.to_owned(), expected.saturating_sub(sent)), stop);
+}
 
-    target
+/// Load the target list, `-` reads it from stdin. See `target::parse_target`.
+pub fn init_target(filename: &str) -> Result<Vec<String>, Error> {
+    load_targets(filename).map_err(|e| Error::io(filename, e))
 }
 
-fn supper(use_mem: f64, pid: u32) -> bool{
-    if (get_use_mem4pid(pid) / mem_total()) * 0.75 >= use_mem {
-        debug!("supper");
-        return true
-    }
-    false
+/// Split `name` into the labels in front of the longest matching target and
+/// that target, both without the trailing dot.
+pub fn split_target(name: &str, targets: &[String]) -> Option<(Vec<String>, String)> {
+    let name = name.trim().trim_end_matches('.').to_lowercase();
+    let zone = targets.iter()
+        .map(|t| t.trim().trim_end_matches('.').to_lowercase())
+        .filter(|t| name.ends_with(&format!(".{}", t)))
+        .max_by_key(|t| t.len())?;
+
+    let labels = name[..name.len() - zone.len() - 1]
+        .split('.')
+        .map(|l| l.to_string())
+        .collect();
+    Some((labels, zone))
 }
 
-fn gen_item(domain: &str, sub: &str, depth: usize) -> Item {
+pub fn gen_item(domain: &str, sub: &str, depth: usize) -> Item {
     let subdomain =joint_subdomain(domain, sub);
 
     Item {
         sub: sub.to_owned(),
         subdomain,
         depth,
-        collect: None
+        collect: None,
+        sample: false,
+        index: None
     }
 }
 
@@ -106,4 +228,49 @@ fn joint_subdomain(domain: &str, sub: &str) -> String {
     }
     let subdomain = sub.to_owned() + "." + domain;
     subdomain
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::{channel, sync_channel};
+    use crate::check_handler::GenItem;
+    use crate::dict::StreamDict;
+
+    #[test]
+    fn test_sample_excluded() {
+        let dir = crate::test_util::TempDir::new();
+        let dict = dir.file("dict.txt", "www\napi\ndev\nmail\n");
+        let mut exclude = Exclusions::new();
+        exclude.add("api.example.com.");
+        exclude.add("dev.example.com.");
+
+        let (gen_send, gen_recv) = channel();
+        let (query_send, query_recv) = sync_channel(16);
+        let (statistical_send, _statistical_recv) = channel();
+        let options = GenOptions {
+            dicts: DictSet::new(StreamDict::new(&[dict], false, false).unwrap()),
+            targets: Vec::new(),
+            exclude,
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        let gen = gen_event(gen_recv, query_send, options, &Checkpoint::default(), statistical_send);
+
+        let items = GenItem {
+            domain: "example.com.".to_string(),
+            depth: 1,
+            skip: 0,
+        };
+        gen_send.send(QueueMessage::Sample(items, 3)).unwrap();
+        gen_send.send(QueueMessage::Terminate).unwrap();
+        gen.join().unwrap();
+
+        let sent: Vec<QueueMessage> = query_recv.iter().collect();
+        let names: Vec<(String, Option<usize>)> = sent.iter().filter_map(|q| match q {
+            QueueMessage::Job(i) => Some((i.subdomain.to_owned(), i.index)),
+            _ => None,
+        }).collect();
+        assert_eq!(names, vec![("www.example.com.".to_string(), Some(0)), ("mail.example.com.".to_string(), Some(1))]);
+        // only the queries actually sent are reported for the sample
+        assert_eq!(sent.contains(&QueueMessage::Complete("example.com.".to_string(), 2)), true);
+    }
+}
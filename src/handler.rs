@@ -1,33 +1,67 @@
 use std::thread;
-use std::sync::mpsc::{Sender, Receiver};
-use std::process::exit;
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Sender, SyncSender, Receiver};
 
 use pool_rs::pool::ThreadPool;
 
 use crate::query::{query_event, Protocol};
+use crate::rate::RateLimit;
 use crate::gen_handler::Item;
 use crate::mem_util::{Statue, QueueMessage};
 
 // use rand::Rng;
 
-pub fn subdomain_query_event(query_recv: Receiver<QueueMessage>, check_send: Sender<QueueMessage>,
-                             worker: usize, retry: usize, statistical_send: Sender<Statue>) -> thread::JoinHandle<()> {
+/// How the query stage resolves names, built by `scanner::ScanBuilder`.
+pub struct QueryOptions {
+    pub worker: usize,
+    pub retry: usize,
+    pub protocol: Protocol,
+    // empty for the built-in public resolvers
+    pub resolvers: Arc<Vec<SocketAddr>>,
+    // names queried per second, 0 for no limit
+    pub rate: usize,
+}
+
+pub fn subdomain_query_event(query_recv: Receiver<QueueMessage>, check_send: SyncSender<QueueMessage>,
+                             options: QueryOptions, stop: Arc<AtomicBool>,
+                             statistical_send: Sender<Statue>) -> thread::JoinHandle<()> {
     thread::spawn(move || {
         debug!("Start subdomain query");
+        let QueryOptions { worker, retry, protocol, resolvers, rate } = options;
+        let mut limit = RateLimit::new(rate);
 
         let pool = ThreadPool::new(worker);
 
+        // one permit per worker: a job is only handed to the pool once a
+        // running one has finished, so `query_recv` backs up instead of the pool
+        let (permit_send, permit_recv) = sync_channel(worker);
+        // `permit_recv` is held here, filling it up to `worker` cannot fail
+        for _ in 0..worker {
+            let _ = permit_send.send(());
+        }
+
         let statistical_send = statistical_send.clone();
 
         for q in query_recv {
             let statistical_send = statistical_send.clone();
             let check = check_send.clone();
+            let protocol = protocol.clone();
+            let resolvers = resolvers.clone();
+            let stop = stop.clone();
 
             match q {
                 QueueMessage::Job(item) => {
+                    // `permit_send` is held here too, waiting cannot fail
+                    let _ = permit_recv.recv();
+                    limit.wait();
+                    let permit = permit_send.clone();
                     let subdomain = item.subdomain.to_owned();
                     let sub = item.sub.to_owned();
                     let depth = item.depth.to_owned();
+                    let sample = item.sample;
+                    let index = item.index;
 
                     pool.execute(move || {
                         drop(item);
@@ -38,32 +72,38 @@ pub fn subdomain_query_event(query_recv: Receiver<QueueMessage>, check_send: Sen
                             }
                         };
                         let mut collect = Vec::new();
-                        query_event(subdomain.as_ref(), &mut collect, retry, Protocol::TCP);
+                        query_event(subdomain.as_ref(), &mut collect, retry, protocol, &resolvers);
 
                         let check_item = Item {
                             sub: sub.to_owned(),
                             subdomain: subdomain.to_owned(),
                             depth: depth.to_owned(),
-                            collect: Some(collect)
-                        };
-                        match check.send(QueueMessage::Job(check_item.to_owned())) {
-                            Ok(_) => {},
-                            Err(e) => {
-                                error!("[handler] send check Job {:?} {:?}", e, check_item);
-                                exit(1)
-                            }
+                            collect: Some(collect),
+                            sample,
+                            index
                         };
-
-
+                        // announced before the hand over, check may be done with it
+                        // before this thread runs again
                         match statistical_send.send(Statue::Checks) {
                             Ok(_) => {},
                             Err(_) => {
                                 error!("[handler] send Statue::Checks {:?}", check_item);
                             }
                         }
-                        drop(check_item);
+
+                        // the check thread is gone, the scan reports it as failed
+                        if let Err(e) = check.send(QueueMessage::Job(check_item)) {
+                            error!("[handler] send check Job {:?}", e);
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                        let _ = permit.send(());
                     });
                 }
+                QueueMessage::Complete(..) => {
+                    if check.send(q).is_err() {
+                        error!("[handler] send check Complete");
+                    }
+                }
                 QueueMessage::Terminate => {
                     debug!("[handler] Terminate");
                     drop(q);
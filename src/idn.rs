@@ -0,0 +1,41 @@
+use idna::{domain_to_ascii, domain_to_unicode};
+
+/// Normalise a name to the lowercase ASCII form sent on the wire. Unicode
+/// labels are converted with IDNA (UTS #46) to punycode. A trailing dot is kept.
+pub fn to_ascii(name: &str) -> Option<String> {
+    let name = name.trim();
+    if name.is_ascii() {
+        return Some(name.to_lowercase())
+    }
+
+    let root = name.ends_with('.');
+    let ascii = domain_to_ascii(name.trim_end_matches('.')).ok()?;
+    if ascii.is_empty() {
+        return None
+    }
+    Some(if root { ascii + "." } else { ascii })
+}
+
+/// The Unicode form of an ASCII name, for display. Labels that are not
+/// valid punycode are left as they are.
+pub fn to_unicode(name: &str) -> String {
+    if !name.contains("xn--") {
+        return name.to_string()
+    }
+    domain_to_unicode(name).0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_idn() {
+        assert_eq!(to_ascii("WWW.Example.com."), Some("www.example.com.".to_string()));
+        assert_eq!(to_ascii("Bücher.example."), Some("xn--bcher-kva.example.".to_string()));
+        assert_eq!(to_ascii("测试"), Some("xn--0zwm56d".to_string()));
+        assert_eq!(to_ascii("a\u{FFFD}b"), None);
+        assert_eq!(to_unicode("xn--bcher-kva.example"), "bücher.example");
+        assert_eq!(to_unicode("www.example.com"), "www.example.com");
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::fs::OpenOptions;
+use std::collections::HashSet;
+
+use crate::dict_set::DictWords;
+use crate::query::gen_subdomain;
+use crate::gen_handler::split_target;
+
+/// Collects dictionary words from names discovered during the scan.
+///
+/// Every label in front of a target zone is learned, together with its
+/// `-` separated parts and their alphabetic runs, so `api-dev2.eu.example.com`
+/// yields `api-dev2`, `api`, `dev2`, `dev` and `eu`.
+#[derive(Clone, Debug)]
+pub struct Learner {
+    targets: Vec<String>,
+    known: HashSet<String>,
+    learned: Vec<String>,
+    output: Option<String>,
+    dictionary: Arc<DictWords>,
+}
+
+impl Learner {
+    /// With `output` the learned words are written there by `save`.
+    pub fn new(targets: &[String], output: Option<String>) -> Self {
+        Self {
+            targets: targets.to_vec(),
+            known: HashSet::new(),
+            learned: Vec::new(),
+            output,
+            dictionary: Arc::new(DictWords::default()),
+        }
+    }
+
+    /// The sub dictionaries, words they already try below every target are not learned.
+    pub fn dictionary(&mut self, dictionary: Arc<DictWords>) {
+        self.dictionary = dictionary;
+    }
+
+    /// Take back the words learned before the scan was resumed.
+    pub fn restore(&mut self, words: &[String]) {
+        for word in words {
+            if self.known.insert(word.to_owned()) {
+                self.learned.push(word.to_owned());
+            }
+        }
+    }
+
+    /// Mark a word as already tried, e.g. the dictionary word behind a result.
+    pub fn observe(&mut self, word: &str) {
+        if !word.is_empty() {
+            self.known.insert(word.to_string());
+        }
+    }
+
+    /// Learn from a result name or a CNAME target. Names outside every target
+    /// zone and IP addresses are ignored. Returns the words seen for the first time.
+    pub fn learn(&mut self, name: &str) -> Vec<String> {
+        if name.parse::<IpAddr>().is_ok() {
+            return Vec::new()
+        }
+
+        let labels = match split_target(name, &self.targets) {
+            Some((labels, _)) => labels,
+            None => return Vec::new(),
+        };
+
+        let mut words = Vec::new();
+        for label in labels {
+            for word in tokens(label.as_str()) {
+                if self.known.contains(&word) || gen_subdomain(word.as_str(), ".1.com").is_none() {
+                    continue
+                }
+                if self.targets.iter().all(|t| self.dictionary.contains(t.as_str(), 1, word.as_str())) {
+                    self.known.insert(word);
+                    continue
+                }
+                self.known.insert(word.to_owned());
+                self.learned.push(word.to_owned());
+                words.push(word);
+            }
+        }
+        words
+    }
+
+    pub fn len(&self) -> usize {
+        self.learned.len()
+    }
+
+    pub fn targets(&self) -> &[String] {
+        &self.targets
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let filename = match self.output.as_ref() {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)?;
+        for word in self.learned.iter() {
+            file.write_all(word.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+fn tokens(label: &str) -> Vec<String> {
+    let mut tokens = vec![label.to_string()];
+    for part in label.split('-') {
+        tokens.push(part.to_string());
+        for run in part.split(|c: char| c.is_ascii_digit()) {
+            tokens.push(run.to_string());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    tokens.into_iter()
+        .filter(|t| !t.is_empty() && !t.chars().all(|c| c.is_ascii_digit()))
+        .filter(|t| seen.insert(t.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_learn() {
+        let mut l = Learner::new(&["example.com.".to_string()], None);
+        l.observe("eu");
+        assert_eq!(l.learn("api-dev2.eu.example.com."), vec!["api-dev2", "api", "dev2", "dev"]);
+        assert_eq!(l.learn("dev.example.com"), Vec::<String>::new());
+        assert_eq!(l.learn("cdn.example.net"), Vec::<String>::new());
+        assert_eq!(l.learn("1.2.3.4"), Vec::<String>::new());
+        assert_eq!(l.len(), 4);
+    }
+
+    #[test]
+    fn test_learn_dictionary() {
+        let dir = crate::test_util::TempDir::new();
+        dir.cache();
+        let dict = dir.file("dict.txt", "api\ndev\n");
+        let dicts = crate::dict_set::DictSet::new(crate::dict::StreamDict::new(&[dict.to_owned()], false, false).unwrap());
+
+        let mut l = Learner::new(&["example.com.".to_string()], None);
+        l.dictionary(Arc::new(DictWords::new(&dicts)));
+        assert_eq!(l.learn("api-dev2.example.com."), vec!["api-dev2", "dev2"]);
+    }
+}
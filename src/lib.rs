@@ -1,12 +1,36 @@
 #[macro_use] extern crate log;
 
 #[doc(hidden)] #[macro_use] pub mod logger;
+pub mod checkpoint;
+pub mod error;
 pub mod cli;
+pub mod config_file;
 pub mod dict;
+pub mod dict_index;
+pub mod dict_set;
+pub mod dict_tool;
+pub mod exclude;
+pub mod idn;
+pub mod depth_grow;
+pub mod learn;
 pub mod query;
+pub mod rate;
+pub mod target;
+pub mod pattern;
+pub mod psl;
+pub mod permutation;
+pub mod sampling;
+pub mod signal;
 pub mod mem_util;
 pub mod handler;
 pub mod wildcards;
+pub mod work;
+pub mod report;
+pub mod resolve;
+pub mod scanner;
 pub mod gen_handler;
 pub mod write_handler;
-pub mod check_handler;
\ No newline at end of file
+pub mod check_handler;
+
+#[cfg(test)]
+mod test_util;
\ No newline at end of file
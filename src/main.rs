@@ -4,39 +4,134 @@
 
 use yansi::Paint;
 
-use baddns::cli::{show_logo, Config};
-use baddns::dict::Dict;
-use baddns::wildcards::{Wildcards, wildcards_event};
-use indicatif::ProgressBar;
-use std::sync::mpsc::channel;
-use baddns::write_handler::write_event;
-use baddns::check_handler::check_event;
-use baddns::gen_handler::{gen_event, init_target};
-use baddns::handler::subdomain_query_event;
-use baddns::mem_util::state_management;
+use baddns::cli::{show_logo, Config, DepthPolicy, Mode};
+use baddns::dict::{Dict, StreamDict};
+use baddns::dict_set::DictSet;
+use baddns::dict_tool::clean;
+use baddns::exclude::Exclusions;
+use baddns::psl::SuffixList;
+use baddns::checkpoint::Checkpoint;
+use std::time::Duration;
+use std::process::exit;
+use baddns::signal::{self, interrupted, EXIT_INTERRUPTED};
+use std::env::temp_dir;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use baddns::wildcards::Wildcards;
+use baddns::write_handler::{write_event, write_report};
+use baddns::gen_handler::init_target;
+use baddns::scanner::ScanBuilder;
+use baddns::error::Error;
+use baddns::resolve::resolve;
+use baddns::report::{load_results, merge, render};
 
 fn main() {
-    show_logo();
-    let c = Config::new();
+    let c = match Config::new() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("{}", e);
+            info!("For more information try --help");
+            exit(1)
+        }
+    };
 
-    launch_info!("[1/5] {} Configured for sub dict",  Paint::masked("🔧"));
-    let sub_dict = Dict::new(c.get_sub_dict_file());
-    info!("{} Load sub dict: {}", Paint::masked("✅ "), sub_dict.len());
+    // printed before the logo so the output can be saved as a config file
+    if c.get_print_config() {
+        print!("{}", c.get_effective());
+        return
+    }
+    // a report may go to stdout
+    if c.get_mode() != &Mode::Report {
+        show_logo();
+    }
+
+    match c.get_mode() {
+        Mode::Dict => return dict_tool(&c),
+        Mode::Report => return report_tool(&c),
+        _ => {}
+    }
 
-    launch_info!("[2/5] {} Configured for depth dict", Paint::masked("🔧"));
-    let depth = Dict::new(c.get_depth_dict_file());
+    signal::install();
+
+    if c.get_mode() == &Mode::Resolve {
+        return resolve_tool(&c);
+    }
+
+    launch_info!("[1/5] {} Configured for depth dict", Paint::masked("🔧"));
+    let depth = fatal(Dict::new(c.get_depth_dict_file()));
     info!("{} Load depth dict: {}", Paint::masked("✅ "), depth.len());
 
-    launch_info!("[3/5] {} Create a thread pool", Paint::masked("🔧"));
+    launch_info!("[2/5] {} Create a thread pool", Paint::masked("🔧"));
     info!("{} Create {} threads", Paint::masked("✅ "), c.get_worker());
 
-    launch_info!("[4/5] {} Initialization target", Paint::masked("🔧"));
-    let target = init_target(c.get_target_file());
-    info!("{} target count: {}",Paint::masked("✅ "), target.len());
+    launch_info!("[3/5] {} Initialization target", Paint::masked("🔧"));
+    let psl = match c.get_psl_file() {
+        Some(f) => fatal(SuffixList::load(f.as_str()).map_err(|e| Error::io(f, e))),
+        None => SuffixList::embedded(),
+    };
+    let target = fatal(init_target(c.get_target_file()));
+    let exclude = load_exclusions(&c);
+
+    launch_info!("[4/5] {} Configured for sub dict",  Paint::masked("🔧"));
+    let sub_dict = fatal(StreamDict::new(c.get_sub_dict_file(), c.get_dedupe(), c.get_ranked()));
+    info!("{} Load sub dict: {}", Paint::masked("✅ "), sub_dict.len());
+    let mut dicts = DictSet::new(sub_dict);
+    for (selector, files) in c.get_dict_rules() {
+        let d = fatal(StreamDict::new(files, c.get_dedupe(), c.get_ranked()));
+        info!("{} Load sub dict for {:?}: {}", Paint::masked("✅ "), selector, d.len());
+        dicts.add_rule(selector.clone(), d);
+    }
+
+    let mut builder = ScanBuilder::new(target, dicts)
+        .depth_dict(depth)
+        .depth(c.get_depth())
+        .worker(c.get_worker())
+        .retry(c.get_retry())
+        .protocol(c.get_protocol().clone())
+        .resolvers(c.get_resolvers().clone())
+        .rate(c.get_rate())
+        .queue_size(c.get_queue_size())
+        .wildcards(Wildcards::load(c.get_wildcard_cache(), c.get_cache_ttl()), c.get_refresh())
+        .exclude(exclude, c.get_exclude_mode().clone())
+        .public_suffix(psl, c.get_suffix_policy().clone(), c.get_registrable())
+        .progress(true);
+
+    if c.get_permute() {
+        let words = fatal(Dict::new(c.get_permute_words())).get_dict();
+        info!("{} Load permutation words: {}", Paint::masked("✅ "), words.len());
+        builder = builder.permute(words, c.get_permute_cap());
+    }
+    if c.get_learn() {
+        builder = builder.learn(c.get_learn_output().clone());
+    }
+    if c.get_grow_depth() {
+        let passive = match c.get_passive_file() {
+            Some(f) => fatal(init_target(f.as_str())),
+            None => Vec::new(),
+        };
+        builder = builder.grow_depth(c.get_depth_output().to_owned(), passive);
+    }
+    if c.get_depth_policy() == &DepthPolicy::Sample {
+        builder = builder.sample(c.get_sample_size(), c.get_sample_threshold());
+    }
+
+    if c.get_resume() {
+        let r = fatal(Checkpoint::load(c.get_checkpoint_file()).map_err(|e| Error::io(c.get_checkpoint_file(), e)));
+        info!("{} Resume {} expansions, {} queries, {} results", Paint::masked("✅ "),
+              r.pending.len(), r.candidates.len(), r.results.len());
+        builder = builder.resume(r);
+    }
+    if c.get_checkpoint_interval() > 0 {
+        builder = builder.checkpoint(c.get_checkpoint_file(), Duration::from_secs(c.get_checkpoint_interval()));
+    }
 
     launch_info!("[5/5] {} Initialization whitelist", Paint::masked("🔧"));
-    let mut whitelist = Wildcards::new();
-    wildcards_event(target.clone(), depth.clone().get_dict(), c.get_worker(), &mut whitelist);
+    let scanner = builder.build();
+    info!("{} target count: {}",Paint::masked("✅ "), scanner.targets().len());
+    let whitelist = scanner.wildcards();
+    if let Err(e) = whitelist.save(c.get_wildcard_cache()) {
+        warn!("Save wildcard cache {} msg: {:?}", c.get_wildcard_cache(), e);
+    }
     let w = whitelist.clone().get_list();
     if w.len() > 0 {
         info!("{} Collected {} whitelist records, Show whitelist:", Paint::masked("✅ "), w.len());
@@ -45,48 +140,113 @@ fn main() {
         info!("{} No whitelist", Paint::masked("✅️ "));
     }
 
-    launch_info!("{} ignition...", Paint::masked("🚀 "));
-
-    // create progressbar
-    let pb = ProgressBar::new(0);
+    if c.get_mode() == &Mode::Wildcard {
+        let reports = whitelist.report(scanner.targets());
+        let wildcarded = reports.iter().filter(|r| r.wildcard).count();
+        fatal(write_report(c.get_output_file(), &reports).map_err(|e| Error::io(c.get_output_file(), e)));
+        info!("{} {} of {} zones are wildcarded, report saved to {}",
+              Paint::masked("✅ "), wildcarded, reports.len(), c.get_output_file());
+        return
+    }
 
-    // create channel
-    let (gen_send, gen_recv) = channel();
-    let (result_send, result_recv) = channel();
-    let (check_send, check_recv) = channel();
+    if interrupted() {
+        exit(EXIT_INTERRUPTED);
+    }
 
-    let (query_send, query_recv) = channel();
+    launch_info!("{} ignition...", Paint::masked("🚀 "));
 
-    // statue
-    let (statistical_send, statistical_recv) = channel();
+    fatal(write_event(c.get_output_file(), scanner.start()));
 
-    let mem_statue = state_management(statistical_recv, gen_send.clone(), pb, sub_dict.len(), target.len());
-    let query_handler = subdomain_query_event(query_recv, check_send, c.get_worker(), c.get_retry(), statistical_send.clone());
+    if interrupted() {
+        info!("Interrupted, results saved to {}", c.get_output_file());
+        exit(EXIT_INTERRUPTED);
+    }
+}
 
-    let write_handler = write_event(c.get_output_file().to_owned(), result_recv, statistical_send.clone());
+// The library returns errors, the binary reports them and exits.
+fn fatal<T>(r: Result<T, Error>) -> T {
+    match r {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}", e);
+            exit(1)
+        }
+    }
+}
 
-    let check_handler = check_event(check_recv, gen_send, result_send,
-                                    c.get_depth(), depth, whitelist, statistical_send.clone());
+fn load_exclusions(c: &Config) -> Exclusions {
+    let exclude = match c.get_exclude_file() {
+        Some(f) => match Exclusions::load(f.as_str()) {
+            Ok(e) => e,
+            Err(e) => {
+                error!("{} {}", f, e);
+                exit(1)
+            }
+        },
+        None => Exclusions::new(),
+    };
+    if !exclude.is_empty() {
+        info!("{} Load {} exclusions", Paint::masked("✅ "), exclude.len());
+    }
+    exclude
+}
 
-    let gen_handler = gen_event(gen_recv, query_send, sub_dict.get_dict(), target, statistical_send, c.get_pid(), c.get_use_mem());
+// `baddns resolve`: query every name of the target file once, without a dictionary
+fn resolve_tool(c: &Config) {
+    let mut names = fatal(init_target(c.get_target_file()));
+    let exclude = load_exclusions(c);
+    names.retain(|n| !exclude.is_excluded(n.as_str()));
+    info!("{} Resolve {} names with {} threads", Paint::masked("✅ "), names.len(), c.get_worker());
 
+    let results = resolve(names, c.get_worker(), c.get_retry(), c.get_protocol().clone(), c.get_resolvers().clone());
+    fatal(write_event(c.get_output_file(), results.map(Ok)));
 
+    if interrupted() {
+        info!("Interrupted, results saved to {}", c.get_output_file());
+        exit(EXIT_INTERRUPTED);
+    }
+}
 
-    mem_statue.join().unwrap();
-    debug!("mem_statue end");
-    write_handler.join().unwrap();
-    debug!("write_handler end");
+// `baddns report`: merge result files and convert them to another format
+fn report_tool(c: &Config) {
+    let mut results = Vec::new();
+    for f in c.get_report_files() {
+        results.extend(fatal(load_results(f.as_str())));
+    }
+    let results = merge(results);
+
+    match c.get_report_output() {
+        Some(o) => {
+            let written = File::create(o)
+                .and_then(|f| render(&results, c.get_report_format(), BufWriter::new(f)));
+            fatal(written.map_err(|e| Error::io(o, e)));
+            info!("{} {} names saved to {}", Paint::masked("✅ "), results.len(), o);
+        }
+        None => {
+            let stdout = io::stdout();
+            fatal(render(&results, c.get_report_format(), stdout.lock()).map_err(|e| Error::io("stdout", e)));
+        }
+    }
+}
 
-    match check_handler.join() {
-        Ok(_) => {},
-        Err(e) => {debug!("adda {:?}", e)}
+// `baddns dict`: report invalid entries, then merge and deduplicate into the output
+fn dict_tool(c: &Config) {
+    let output = match c.get_dict_output() {
+        Some(o) => o.to_owned(),
+        None => temp_dir().join(format!("baddns-dict-{}.txt", c.get_pid())).to_string_lossy().to_string(),
     };
-    debug!("check_handler end");
-    gen_handler.join().unwrap();
-    debug!("gen_handler end");
-    query_handler.join().unwrap();
-    debug!("query_handler end");
 
+    let stats = clean(c.get_dict_files(), output.as_str(), |file, n, line, e| {
+        println!("{}:{}: {}: {:?}", file, n, e, line);
+    });
 
+    if c.get_dict_output().is_none() {
+        let _ = fs::remove_file(output.as_str());
+    }
 
+    let stats = fatal(stats.map_err(|e| Error::io(c.get_dict_files().join(",").as_str(), e)));
+    println!("{}", stats);
+    if c.get_dict_output().is_some() {
+        info!("{} Cleaned dictionary saved to {}", Paint::masked("✅ "), output);
+    }
 }
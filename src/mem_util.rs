@@ -1,168 +1,134 @@
 use std::thread;
-use std::fs::File;
-use std::process::exit;
-use std::io::{BufReader, BufRead};
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 
 use indicatif::ProgressBar;
 
 use crate::check_handler::GenItem;
 use crate::gen_handler::Item;
+use crate::work::Work;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum QueueMessage {
     Job(Item),
     Gen(GenItem),
-    Clear,
+    // queries registered with `Statue::Expand` under the name
+    Candidates(String, Vec<Item>),
+    Learned(Vec<String>),
+    Sample(GenItem, usize),
+    // gen generated every candidate of the expansion or sample below the domain, numbered 0..n
+    Complete(String, usize),
     Terminate,
-    Sleep,
-    Break,
 }
 
 
+/// Progress of the scan, see `work::Work` for how it is accounted.
 pub enum Statue {
-    Check,
-    // Gen,
+    // an expansion below the name was handed to gen, expected to emit n queries
+    Expand(String, usize),
+    // gen finished the expansion below the name, emitting n fewer queries than expected
+    Expanded(String, usize),
+    Queued(String),
     Query,
-    Write,
-    Unwrite,
-    Terminate,
-    Querys,
-    Writes,
     Checks,
-    TargetCount,
-}
-
-pub fn mem_total() -> f64 {
-    let f = match File::open("/proc/meminfo") {
-        Ok(f) => f,
-        Err(e) => {
-            println!("[util] Failure mem_total,Currently only supports Linux. msg:{:?}", e);
-            exit(1);
-        }
-    };
-
-    get_use_mem4field(f, "MemTotal:")
-
-}
-
-pub fn get_use_mem4pid(pid: u32) -> f64 {
-    let file_path = "/proc/".to_owned() + pid.to_string().as_str() + "/status";
-    let f = match File::open(file_path) {
-        Ok(f) => f,
-        Err(e) => {
-            println!("[util] Failure get_use_mem4pid ,Currently only supports Linux. msg:{:?}", e);
-            exit(1)
-        }
-    };
-    get_use_mem4field(f, "VmRSS:")
+    // check finished with the name and everything it created was announced
+    Done(String),
+    Terminate,
 }
 
-pub fn get_use_mem4field(f: File, field: &str) -> f64 {
-    let reader = BufReader::new(f);
-    for line in reader.lines() {
-        match line {
-            Ok(line) => {
-                if line.is_empty() {continue}
-                let mut s = line.split_whitespace();
-                if s.next().unwrap() == field {
-                    return s.next().unwrap().parse().unwrap()
-                }
-            }
-            Err(_) => {}
-        }
+/// Report progress to the stats thread. It is only gone if it panicked,
+/// the scan can never be seen as finished then, so it is stopped.
+pub fn send_statue(statistical_send: &Sender<Statue>, statue: Statue, stop: &AtomicBool) {
+    if statistical_send.send(statue).is_err() {
+        error!("[mem_util] send Statue, stopping the scan");
+        stop.store(true, Ordering::SeqCst);
     }
-    println!("[util] Failure get_use_mem4field,Currently only supports Linux");
-    exit(1);
 }
 
 pub fn state_management(
     statistical_recv: Receiver<Statue>,
     items: Sender<QueueMessage>,
     pb: ProgressBar,
-    sub_len: usize,
-    target_len: usize) -> thread::JoinHandle<()> {
+    targets: Vec<String>,
+    stop: Arc<AtomicBool>) -> thread::JoinHandle<()> {
 
     thread::spawn(move || {
+        let mut work = Work::new(&targets);
+        let mut completed = 0;
 
-        // all
-        let mut checks = 0;
-        let mut querys = 0;
-        let mut _writes = 0;
-        let mut target = 0;
-
-        // complete
-        let mut check= 0;
-        let mut query = 0;
-        let mut write =0;
-        let mut un_write = 0;
-
-        let mut terminate = false;
         let mut terminate_send_statue = false;
+        let mut stop_reported = false;
 
-        let mut alls = ((target_len * sub_len) + target_len) as u64;
+        // expansions announce their expected size, the bar is only an estimate
+        let mut alls = 0u64;
         pb.set_length(alls);
 
-        for i in statistical_recv {
-            match i {
-                Statue::TargetCount => {
-                    target += 1;
-                    alls = (((target + target_len) * sub_len) + target_len)as u64;
-                    //  (10 + 5)* 1751385 + 10
-                    pb.set_length(alls)
-                }
-                Statue::Checks => {
-                    checks +=1
-                }
-                Statue::Querys => {
-                    querys += 1;
+        loop {
+            let i = match statistical_recv.recv_timeout(Duration::from_secs(30)) {
+                Ok(i) => i,
+                Err(RecvTimeoutError::Timeout) => {
+                    debug!("[mem_util] waiting on {}", work.remaining());
+                    continue
                 }
-                Statue::Writes => {
-                    _writes += 1
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            let complete = match i {
+                Statue::Expand(name, n) => {
+                    work.expand(name.as_str());
+                    alls += n as u64;
+                    pb.set_length(alls);
+                    None
                 }
-                Statue::Write => {
-                    write += 1
+                Statue::Expanded(name, n) => {
+                    alls = alls.saturating_sub(n as u64);
+                    pb.set_length(alls);
+                    work.expanded(name.as_str())
                 }
-                Statue::Check => {
-                    check += 1
+                Statue::Queued(name) => {
+                    work.queue(name.as_str());
+                    None
                 }
                 Statue::Query => {
-                    query +=1;
+                    work.query();
                     pb.inc(1);
+                    None
                 }
-                Statue::Unwrite => {
-                    un_write +=1;
+                Statue::Checks => {
+                    work.check();
+                    None
                 }
+                Statue::Done(name) => work.done(name.as_str()),
                 Statue::Terminate => {
-                    terminate = true;
-                }
-            }
-
-            // debug!("check {:?}, checks {:?}, query {:?}, querys {:?}, write {:?}, writes {:?}, un-write {:?},alls {:?}",
-            //          check, checks, query, querys, write, writes, un_write, alls);
-
-            let write = write + un_write;
-
-            if querys.eq(&check) && querys.eq(&checks) && querys.eq(&query) && querys.eq(&write) && querys.eq(&alls){
-                if terminate {
-                    debug!("[mem_util] Break");
                     pb.finish();
-                    break
+                    debug!("[mem_util] {} of {} targets complete", completed, targets.len());
+                    None
                 }
+            };
 
-                if !terminate_send_statue {
-                    match items.send(QueueMessage::Terminate) {
-                        Ok(_) => {
-                            debug!("[mem_util] QueueMessage::Terminate");
-                        },
-                        Err(_) => {
-                            error!("[mem_util] send Terminate");
-                        }
-                    };
-                    terminate_send_statue = true;
-                }
+            if let Some(t) = complete {
+                completed += 1;
+                debug!("[mem_util] target {} complete", t);
             }
 
+            if stop.load(Ordering::SeqCst) && !stop_reported && !work.is_idle() {
+                info!("Stopping, remaining {}", work.remaining());
+                stop_reported = true;
+            }
+
+            if work.is_idle() && !terminate_send_statue {
+                match items.send(QueueMessage::Terminate) {
+                    Ok(_) => {
+                        debug!("[mem_util] QueueMessage::Terminate");
+                    },
+                    Err(_) => {
+                        error!("[mem_util] send Terminate");
+                    }
+                };
+                terminate_send_statue = true;
+            }
         }
 
         debug!("[mem_util] break end");
@@ -0,0 +1,270 @@
+// Generator syntax for dictionary entries:
+//
+//   api{1-20}        numeric range, `{01-20}` keeps the zero padding
+//   {dev,stg,prod}   alternatives
+//   ?l ?d ?h ?a      masks: a-z, 0-9, 0-9a-f, a-z0-9
+//
+// Entries are expanded lazily, so a single line can stand for millions of candidates.
+
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const DIGIT: &str = "0123456789";
+const HEX: &str = "0123456789abcdef";
+const ALNUM: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Literal(String),
+    Choice(Vec<String>),
+    Range { start: u64, end: u64, width: usize },
+    Charset(&'static str),
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        match self {
+            Segment::Literal(_) => 1,
+            Segment::Choice(c) => c.len(),
+            // parse_group rejects ranges whose length does not fit
+            Segment::Range { start, end, .. } => (end - start + 1) as usize,
+            Segment::Charset(c) => c.len(),
+        }
+    }
+
+    // Index of the longest element.
+    fn longest(&self) -> usize {
+        match self {
+            Segment::Choice(c) => (0..c.len()).max_by_key(|n| c[*n].len()).unwrap_or(0),
+            Segment::Range { .. } => self.len() - 1,
+            _ => 0,
+        }
+    }
+
+    fn push(&self, n: usize, out: &mut String) {
+        match self {
+            Segment::Literal(s) => out.push_str(s),
+            Segment::Choice(c) => out.push_str(c[n].as_str()),
+            Segment::Range { start, width, .. } => {
+                out.push_str(format!("{:0width$}", start + n as u64, width = *width).as_str())
+            }
+            Segment::Charset(c) => out.push_str(&c[n..n + 1]),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    /// Parse a dictionary entry. Plain words parse as a single literal,
+    /// malformed generator syntax yields `None`.
+    pub fn parse(entry: &str) -> Option<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = entry.chars();
+
+        while let Some(c) = chars.next() {
+            let segment = match c {
+                '{' => {
+                    let mut body = String::new();
+                    loop {
+                        match chars.next()? {
+                            '}' => break,
+                            '{' => return None,
+                            c => body.push(c),
+                        }
+                    }
+                    parse_group(body.as_str())?
+                }
+                '?' => match chars.next()? {
+                    'l' => Segment::Charset(LOWER),
+                    'd' => Segment::Charset(DIGIT),
+                    'h' => Segment::Charset(HEX),
+                    'a' => Segment::Charset(ALNUM),
+                    _ => return None,
+                },
+                '}' => return None,
+                c => {
+                    literal.push(c);
+                    continue
+                }
+            };
+
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(literal.to_owned()));
+                literal.clear();
+            }
+            segments.push(segment);
+        }
+
+        if !literal.is_empty() || segments.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Some(Self {
+            segments
+        })
+    }
+
+    pub fn is_literal(&self) -> bool {
+        self.segments.iter().all(|s| match s {
+            Segment::Literal(_) => true,
+            _ => false,
+        })
+    }
+
+    /// Number of candidates, saturating at `usize::MAX`.
+    pub fn len(&self) -> usize {
+        self.segments.iter().fold(1usize, |n, s| n.saturating_mul(s.len()))
+    }
+
+    pub fn first(&self) -> Option<String> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<String> {
+        if self.len() == 0 {
+            return None
+        }
+        let indexes = self.segments.iter().map(|s| s.len() - 1).collect();
+        Some(self.build(&indexes))
+    }
+
+    /// A few candidates using every alternative and mask character at least once,
+    /// plus the first, last and longest ones. Validating them validates the
+    /// characters and label lengths of every candidate.
+    pub fn samples(&self) -> Vec<String> {
+        if self.len() == 0 {
+            return Vec::new()
+        }
+        let mut samples = vec![self.first(), self.last()].into_iter().flatten().collect::<Vec<_>>();
+        samples.push(self.build(&self.segments.iter().map(|s| s.longest()).collect()));
+
+        let widest = self.segments.iter()
+            .map(|s| match s {
+                Segment::Choice(_) | Segment::Charset(_) => s.len(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        for k in 0..widest {
+            let indexes = self.segments.iter()
+                .map(|s| match s {
+                    Segment::Choice(_) | Segment::Charset(_) => k.min(s.len() - 1),
+                    _ => 0,
+                })
+                .collect();
+            samples.push(self.build(&indexes));
+        }
+        samples.sort();
+        samples.dedup();
+        samples
+    }
+
+    pub fn iter(&self) -> PatternIter {
+        let done = self.len() == 0;
+        PatternIter {
+            pattern: self.clone(),
+            indexes: vec![0; self.segments.len()],
+            done,
+        }
+    }
+
+    fn build(&self, indexes: &Vec<usize>) -> String {
+        let mut out = String::new();
+        for (segment, n) in self.segments.iter().zip(indexes.iter()) {
+            segment.push(*n, &mut out);
+        }
+        out
+    }
+}
+
+pub struct PatternIter {
+    pattern: Pattern,
+    indexes: Vec<usize>,
+    done: bool,
+}
+
+impl Iterator for PatternIter {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.done {
+            return None
+        }
+        let item = self.pattern.build(&self.indexes);
+
+        // Advance the rightmost segment first, carrying to the left.
+        self.done = true;
+        for (n, segment) in self.indexes.iter_mut().zip(self.pattern.segments.iter()).rev() {
+            *n += 1;
+            if *n < segment.len() {
+                self.done = false;
+                break
+            }
+            *n = 0;
+        }
+        Some(item)
+    }
+}
+
+fn parse_group(body: &str) -> Option<Segment> {
+    if body.contains(',') {
+        let choices: Vec<String> = body.split(',').map(|c| c.trim().to_string()).collect();
+        return Some(Segment::Choice(choices))
+    }
+
+    let mut range = body.splitn(2, '-');
+    let (low, high) = (range.next()?.trim(), range.next()?.trim());
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if !digits(low) || !digits(high) {
+        return None
+    }
+
+    let start: u64 = low.parse().ok()?;
+    let end: u64 = high.parse().ok()?;
+    if start > end {
+        return None
+    }
+    // the number of candidates has to fit in a usize
+    let len = (end - start).checked_add(1)?;
+    if len > usize::MAX as u64 {
+        return None
+    }
+    let width = if low.len() > 1 && low.starts_with('0') { low.len() } else { 0 };
+    Some(Segment::Range { start, end, width })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let p = Pattern::parse("api{1-3}").unwrap();
+        assert_eq!(p.iter().collect::<Vec<_>>(), vec!["api1", "api2", "api3"]);
+
+        let p = Pattern::parse("{dev,stg,prod}-web{01-02}").unwrap();
+        assert_eq!(p.len(), 6);
+        assert_eq!(p.first().unwrap(), "dev-web01");
+        assert_eq!(p.last().unwrap(), "prod-web02");
+
+        let p = Pattern::parse("?l?l?d").unwrap();
+        assert_eq!(p.len(), 26 * 26 * 10);
+        assert_eq!(p.iter().nth(11).unwrap(), "ab1");
+
+        assert_eq!(Pattern::parse("www").unwrap().iter().collect::<Vec<_>>(), vec!["www"]);
+        assert_eq!(Pattern::parse("api{1-").is_none(), true);
+        assert_eq!(Pattern::parse("?x").is_none(), true);
+        assert_eq!(Pattern::parse("{0-18446744073709551615}").is_none(), true);
+    }
+
+    #[test]
+    fn test_samples() {
+        let p = Pattern::parse("{a,b!c,d}-{1-100}").unwrap();
+        assert_eq!(p.samples().contains(&"b!c-1".to_string()), true);
+        assert_eq!(p.samples().contains(&"b!c-100".to_string()), true);
+        assert_eq!(Pattern::parse("x?d").unwrap().samples().len(), 10);
+    }
+}
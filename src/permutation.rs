@@ -0,0 +1,202 @@
+use std::sync::Arc;
+use std::collections::HashSet;
+
+use crate::dict_set::DictWords;
+use crate::gen_handler::Item;
+use crate::query::gen_subdomain;
+
+/// Generates altdns style variations of confirmed subdomains.
+///
+/// For a result `api.example.com` and the word `dev` it tries
+/// `dev-api`, `api-dev`, `devapi`, `apidev` (joining), `dev.api` and
+/// `api.dev` (insertion), and for numbered labels such as `web01` the
+/// neighbours `web00` and `web02`. The total number of candidates over
+/// the whole scan is capped.
+#[derive(Clone, Debug)]
+pub struct Permutator {
+    words: Vec<String>,
+    cap: usize,
+    generated: usize,
+    seen: HashSet<String>,
+    dictionary: Arc<DictWords>,
+}
+
+impl Permutator {
+    pub fn new(words: Vec<String>, cap: usize) -> Self {
+        Self {
+            words,
+            cap,
+            generated: 0,
+            seen: HashSet::new(),
+            dictionary: Arc::new(DictWords::default()),
+        }
+    }
+
+    /// The sub dictionaries, variants the brute force of their parent
+    /// already queries are not generated again.
+    pub fn dictionary(&mut self, dictionary: Arc<DictWords>) {
+        self.dictionary = dictionary;
+    }
+
+    pub fn generated(&self) -> usize {
+        self.generated
+    }
+
+    /// Build the candidates for a confirmed result. Names deeper than `max_depth`,
+    /// names already produced and dictionary words below an `expanded` parent are skipped.
+    pub fn permute(&mut self, item: &Item, max_depth: usize, expanded: &HashSet<String>) -> Vec<Item> {
+        self.seen.insert(item.subdomain.to_owned());
+        if self.generated >= self.cap || item.sub.is_empty() {
+            return Vec::new()
+        }
+
+        let parent = match item.subdomain.splitn(2, '.').nth(1) {
+            Some(p) => p.to_string(),
+            None => return Vec::new(),
+        };
+        let label = item.sub.as_str();
+
+        let mut candidates = Vec::new();
+        for sub in numeric_neighbours(label) {
+            candidates.push((sub.to_owned(), sub, item.depth));
+        }
+        for word in self.words.iter() {
+            if word.as_str() == label {
+                continue
+            }
+            for sub in vec![
+                format!("{}-{}", word, label),
+                format!("{}-{}", label, word),
+                format!("{}{}", word, label),
+                format!("{}{}", label, word),
+            ] {
+                candidates.push((sub.to_owned(), sub, item.depth));
+            }
+            if item.depth + 1 <= max_depth {
+                candidates.push((word.to_owned(), format!("{}.{}", word, label), item.depth + 1));
+                candidates.push((label.to_string(), format!("{}.{}", label, word), item.depth + 1));
+            }
+        }
+
+        let mut items = Vec::new();
+        for (sub, prefix, depth) in candidates {
+            if self.generated >= self.cap {
+                break
+            }
+            let subdomain = prefix + "." + parent.as_str();
+            if self.seen.contains(&subdomain) || gen_subdomain(subdomain.as_str(), "").is_none() {
+                continue
+            }
+            let mut labels = subdomain.splitn(2, '.');
+            if let (Some(label), Some(up)) = (labels.next(), labels.next()) {
+                if expanded.contains(up) && self.dictionary.contains(up, depth, label) {
+                    continue
+                }
+            }
+            self.seen.insert(subdomain.to_owned());
+            self.generated += 1;
+            items.push(Item {
+                sub,
+                subdomain,
+                depth,
+                collect: None,
+                sample: false,
+                index: None
+            });
+        }
+        items
+    }
+}
+
+/// Increment and decrement every run of digits in `label`, keeping its width.
+fn numeric_neighbours(label: &str) -> Vec<String> {
+    let bytes = label.as_bytes();
+    let mut neighbours = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+
+        let digits = &label[start..i];
+        let n: u64 = match digits.parse() {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        let width = digits.len();
+        let mut values = Vec::new();
+        if let Some(v) = n.checked_add(1) {
+            values.push(v);
+        }
+        if n > 0 {
+            values.push(n - 1);
+        }
+        for v in values {
+            neighbours.push(format!("{}{:0width$}{}", &label[..start], v, &label[i..], width = width));
+        }
+    }
+    neighbours
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(sub: &str, subdomain: &str) -> Item {
+        Item {
+            sub: sub.to_string(),
+            subdomain: subdomain.to_string(),
+            depth: 1,
+            collect: None,
+            sample: false,
+            index: None
+        }
+    }
+
+    #[test]
+    fn test_permute() {
+        let mut p = Permutator::new(vec!["dev".to_string()], 100);
+        let names: Vec<String> = p.permute(&item("web01", "web01.example.com."), 2, &HashSet::new())
+            .into_iter().map(|i| i.subdomain).collect();
+        assert_eq!(names, vec![
+            "web02.example.com.", "web00.example.com.",
+            "dev-web01.example.com.", "web01-dev.example.com.",
+            "devweb01.example.com.", "web01dev.example.com.",
+            "dev.web01.example.com.", "web01.dev.example.com.",
+        ]);
+
+        // already produced names are not generated twice
+        assert_eq!(p.permute(&item("web02", "web02.example.com."), 1, &HashSet::new()).len(), 5);
+
+        assert_eq!(numeric_neighbours("a18446744073709551615"), vec!["a18446744073709551614"]);
+    }
+
+    #[test]
+    fn test_permute_dictionary() {
+        let dir = crate::test_util::TempDir::new();
+        dir.cache();
+        let dict = dir.file("dict.txt", "dev-api\n");
+        let dicts = crate::dict_set::DictSet::new(crate::dict::StreamDict::new(&[dict.to_owned()], false, false).unwrap());
+
+        let mut p = Permutator::new(vec!["dev".to_string()], 100);
+        p.dictionary(Arc::new(DictWords::new(&dicts)));
+        let expanded = vec!["example.com.".to_string()].into_iter().collect();
+        let names: Vec<String> = p.permute(&item("api", "api.example.com."), 1, &expanded)
+            .into_iter().map(|i| i.subdomain).collect();
+        assert_eq!(names.contains(&"dev-api.example.com.".to_string()), false);
+        assert_eq!(names.contains(&"api-dev.example.com.".to_string()), true);
+    }
+
+    #[test]
+    fn test_permute_cap() {
+        let mut p = Permutator::new(vec!["dev".to_string(), "stg".to_string()], 3);
+        assert_eq!(p.permute(&item("api", "api.example.com."), 2, &HashSet::new()).len(), 3);
+        assert_eq!(p.permute(&item("www", "www.example.com."), 2, &HashSet::new()).len(), 0);
+        assert_eq!(p.generated(), 3);
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::io;
+use std::collections::HashSet;
+
+use crate::idn::to_ascii;
+
+// Snapshot of https://publicsuffix.org/list/public_suffix_list.dat, see `--psl` for a newer one.
+const EMBEDDED: &str = include_str!("../public_suffix_list.dat");
+
+/// What to do with a target that is itself a public suffix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SuffixPolicy {
+    Warn,
+    Refuse,
+}
+
+/// The Public Suffix List: names under which anyone can register a domain,
+/// such as `co.uk` or `github.io`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SuffixList {
+    rules: HashSet<String>,
+    // `*.ck` is stored as `ck`
+    wildcards: HashSet<String>,
+    // `!www.ck` is stored as `www.ck`
+    exceptions: HashSet<String>,
+}
+
+impl SuffixList {
+    pub fn embedded() -> Self {
+        Self::parse(EMBEDDED)
+    }
+
+    pub fn load(filename: &str) -> io::Result<Self> {
+        Ok(Self::parse(fs::read_to_string(filename)?.as_str()))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut list = Self::default();
+        for line in text.lines() {
+            let rule = line.split_whitespace().next().unwrap_or("");
+            if rule.is_empty() || rule.starts_with("//") {
+                continue
+            }
+
+            if let Some(rule) = rule.strip_prefix('!') {
+                list.exceptions.extend(to_ascii(rule));
+            } else if let Some(rule) = rule.strip_prefix("*.") {
+                list.wildcards.extend(to_ascii(rule));
+            } else {
+                list.rules.extend(to_ascii(rule));
+            }
+        }
+        list
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len() + self.wildcards.len() + self.exceptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The public suffix of `name`, without a trailing dot. Names matching
+    /// no rule fall back to their top level label.
+    pub fn suffix(&self, name: &str) -> String {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let labels: Vec<&str> = name.split('.').collect();
+
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(&candidate) {
+                return labels[i + 1..].join(".")
+            }
+            if self.rules.contains(&candidate) {
+                return candidate
+            }
+            if i + 1 < labels.len() && self.wildcards.contains(&labels[i + 1..].join(".")) {
+                return candidate
+            }
+        }
+        labels.last().map(|l| l.to_string()).unwrap_or_default()
+    }
+
+    pub fn is_public_suffix(&self, name: &str) -> bool {
+        self.suffix(name) == name.trim_end_matches('.').to_lowercase()
+    }
+
+    /// The registrable domain of `name`: its public suffix plus one label.
+    /// None when `name` is a public suffix itself.
+    pub fn registrable(&self, name: &str) -> Option<String> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let suffix = self.suffix(name.as_str());
+        if suffix == name {
+            return None
+        }
+        let head = &name[..name.len() - suffix.len() - 1];
+        let label = head.rsplit('.').next()?;
+        Some(format!("{}.{}", label, suffix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_suffix() {
+        let psl = SuffixList::parse("// comment\ncom\nco.uk\ngithub.io\n*.ck\n!www.ck\n公司.cn\n");
+        assert_eq!(psl.suffix("www.example.co.uk."), "co.uk");
+        assert_eq!(psl.is_public_suffix("github.io."), true);
+        assert_eq!(psl.is_public_suffix("user.github.io."), false);
+        assert_eq!(psl.is_public_suffix("anything.ck"), true);
+        assert_eq!(psl.is_public_suffix("www.ck"), false);
+        assert_eq!(psl.is_public_suffix("xn--55qx5d.cn"), true);
+        assert_eq!(psl.registrable("a.b.example.co.uk."), Some("example.co.uk".to_string()));
+        assert_eq!(psl.registrable("a.www.ck"), Some("www.ck".to_string()));
+        assert_eq!(psl.registrable("example.unknown"), Some("example.unknown".to_string()));
+        assert_eq!(psl.registrable("co.uk"), None);
+    }
+
+    #[test]
+    fn test_embedded() {
+        let psl = SuffixList::embedded();
+        assert_eq!(psl.is_public_suffix("co.uk"), true);
+        assert_eq!(psl.is_public_suffix("github.io"), true);
+        assert_eq!(psl.registrable("www.example.com."), Some("example.com".to_string()));
+    }
+}
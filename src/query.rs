@@ -5,11 +5,12 @@ use std::thread;
 
 
 use rand::Rng;
+use rand::seq::SliceRandom;
 use trust_dns_client::op::DnsResponse;
 use trust_dns_client::udp::UdpClientConnection;
 use trust_dns_client::tcp::TcpClientConnection;
 use trust_dns_client::client::{SyncClient, Client};
-use trust_dns_client::rr::{Name, RecordType, DNSClass};
+use trust_dns_client::rr::{Name, Record, RecordType, DNSClass};
 
 pub static ALL_DNS_SERVER: [&str;21] = [
     "8.8.8.8:53", "8.8.4.4:53", "1.1.1.1:53", "1.0.0.1:53", "1.1.1.2:53", "1.0.0.2:53", "1.1.1.3:53",
@@ -25,7 +26,7 @@ pub static TCP_SERVER: [&str; 8] = ["8.8.8.8:53", "8.8.4.4:53", "1.1.1.1:53", "1
     "1.1.1.2:53", "1.0.0.2:53", "1.1.1.3:53", "1.0.0.3:53"];
 
 
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Protocol {
     TCP,
     UDP,
@@ -38,15 +39,17 @@ pub enum RecordTypes {
     AAAA,
 }
 
-pub fn query_event(subdomain: &str, collect: &mut Vec<String>, retry: usize, protocol: Protocol) {
+/// Query the A and CNAME records of `subdomain` from a random server of
+/// `resolvers`, of the built-in public ones when it is empty.
+pub fn query_event(subdomain: &str, collect: &mut Vec<String>, retry: usize, protocol: Protocol, resolvers: &[SocketAddr]) {
     debug!("[query] query_event Start query");
     match Name::from_str(subdomain) {
         Ok(n) => {
             // A
-            query_main(&n, RecordTypes::A, protocol.clone(), collect, retry, 0);
+            query_main(&n, RecordTypes::A, protocol.clone(), collect, retry, 0, resolvers);
 
             // CNAME
-            query_main(&n, RecordTypes::CNAME, protocol, collect, retry, 0);
+            query_main(&n, RecordTypes::CNAME, protocol, collect, retry, 0, resolvers);
         }
         Err(e) => {
             warn!("[query] query_event. msg: {:?}", e.kind());
@@ -55,7 +58,7 @@ pub fn query_event(subdomain: &str, collect: &mut Vec<String>, retry: usize, pro
 }
 
 fn query_main(subdomain: &Name, t:RecordTypes, protocol: Protocol,
-              collect: &mut Vec<String>, retry: usize, count: usize) {
+              collect: &mut Vec<String>, retry: usize, count: usize, resolvers: &[SocketAddr]) {
 
     let rt = match t {
         RecordTypes::CNAME => RecordType::CNAME,
@@ -65,7 +68,7 @@ fn query_main(subdomain: &Name, t:RecordTypes, protocol: Protocol,
 
     match protocol {
         Protocol::TCP => {
-            let client = tcp_connection();
+            let client = tcp_connection(resolvers);
             match client.query(subdomain, DNSClass::IN, rt) {
                 Ok(q) => {
                     query_response_handler(q, collect);
@@ -73,12 +76,12 @@ fn query_main(subdomain: &Name, t:RecordTypes, protocol: Protocol,
                 Err(_) => {
                     thread::sleep(Duration::from_secs_f32(0.3));
                     // let count= count + 1;
-                    query_main(subdomain, t, protocol, collect, retry, count)
+                    query_main(subdomain, t, protocol, collect, retry, count, resolvers)
                 }
             }
         }
         Protocol::UDP => {
-            let client = udp_connection(count);
+            let client = udp_connection(count, resolvers);
             match client.query(subdomain, DNSClass::IN, rt) {
                 Ok(q) => {
                     query_response_handler(q, collect);
@@ -88,9 +91,9 @@ fn query_main(subdomain: &Name, t:RecordTypes, protocol: Protocol,
                     let count  = count + 1;
 
                     if count == retry {
-                        query_main(subdomain, t.clone(), Protocol::TCP, collect, retry, 0);
+                        query_main(subdomain, t.clone(), Protocol::TCP, collect, retry, 0, resolvers);
                     }
-                    query_main(subdomain, t, protocol, collect, retry, count);
+                    query_main(subdomain, t, protocol, collect, retry, count, resolvers);
 
                     // let r = e.kind();
                     // match r {
@@ -116,42 +119,59 @@ fn query_main(subdomain: &Name, t:RecordTypes, protocol: Protocol,
 pub fn query_response_handler(q: DnsResponse, collect: &mut Vec<String>) {
     if !q.is_empty() {
         for i in q.answers() {
-            if i.rr_type().is_cname() {
-                match i.rdata().as_cname() {
-                    Some(a) => {collect.push(a.to_string().trim_end_matches('.').to_string())}
-                    None => {}
-                }
-            }
-            if i.rr_type().is_ip_addr() {
-                match i.rdata().to_ip_addr() {
-                    Some(ip) => {collect.push(ip.to_string())}
-                    None => {}
-                }
-            }
+            query_response_handler_record(i, collect);
         }
     }
 }
 
-fn udp_connection(count: usize) -> SyncClient<UdpClientConnection> {
-    match UdpClientConnection::with_timeout(rand_dns_server(count), Duration::from_secs_f32(60.0)) {
+pub fn query_response_handler_record(i: &Record, collect: &mut Vec<String>) {
+    if i.rr_type().is_cname() {
+        match i.rdata().as_cname() {
+            Some(a) => {collect.push(a.to_string().trim_end_matches('.').to_string())}
+            None => {}
+        }
+    }
+    if i.rr_type().is_ip_addr() {
+        match i.rdata().to_ip_addr() {
+            Some(ip) => {collect.push(ip.to_string())}
+            None => {}
+        }
+    }
+}
+
+fn udp_connection(count: usize, resolvers: &[SocketAddr]) -> SyncClient<UdpClientConnection> {
+    let server = match resolvers.choose(&mut rand::thread_rng()) {
+        Some(s) => *s,
+        None => rand_dns_server(count),
+    };
+    match UdpClientConnection::with_timeout(server, Duration::from_secs_f32(60.0)) {
         Ok(c) => SyncClient::new(c),
         Err(e) => {
             warn!("[query] udp_connection. msg: {:?}", e.kind());
-            udp_connection(count)
+            udp_connection(count, resolvers)
         }
     }
 }
 
-fn tcp_connection() -> SyncClient<TcpClientConnection> {
-    match TcpClientConnection::with_timeout(rand_tcp_dns_server(), Duration::from_secs_f32(120.0)) {
+fn tcp_connection(resolvers: &[SocketAddr]) -> SyncClient<TcpClientConnection> {
+    let server = match resolvers.choose(&mut rand::thread_rng()) {
+        Some(s) => *s,
+        None => rand_tcp_dns_server(),
+    };
+    match TcpClientConnection::with_timeout(server, Duration::from_secs_f32(120.0)) {
         Ok(c) => SyncClient::new(c),
         Err(e) => {
             warn!("[query] tcp_connection. msg: {:?}", e);
-            tcp_connection()
+            tcp_connection(resolvers)
         }
     }
 }
 
+/// The built-in public resolvers, those probed for wildcards by default.
+pub fn default_resolvers() -> Vec<SocketAddr> {
+    ALL_DNS_SERVER.iter().filter_map(|s| s.parse().ok()).collect()
+}
+
 fn rand_dns_server(count: usize) -> SocketAddr {
     let mut rng = rand::thread_rng();
     if count < 2 {
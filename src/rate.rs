@@ -0,0 +1,57 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Spaces out queries to at most `rate` per second, 0 does not limit.
+/// Queries that fell behind are not sent in a burst to catch up.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    interval: Option<Duration>,
+    next: Instant,
+}
+
+impl RateLimit {
+    pub fn new(rate: usize) -> Self {
+        Self {
+            interval: match rate {
+                0 => None,
+                n => Some(Duration::from_secs(1) / n.min(u32::MAX as usize) as u32),
+            },
+            next: Instant::now(),
+        }
+    }
+
+    /// Block until the next query may start.
+    pub fn wait(&mut self) {
+        let interval = match self.interval {
+            Some(i) => i,
+            None => return,
+        };
+        let now = Instant::now();
+        if self.next > now {
+            sleep(self.next - now);
+        }
+        self.next = self.next.max(now) + interval;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let start = Instant::now();
+        let mut r = RateLimit::new(100);
+        for _ in 0..11 {
+            r.wait();
+        }
+        assert_eq!(start.elapsed() >= Duration::from_millis(100), true);
+
+        let start = Instant::now();
+        let mut r = RateLimit::new(0);
+        for _ in 0..1000 {
+            r.wait();
+        }
+        assert_eq!(start.elapsed() < Duration::from_millis(100), true);
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::io::{self, Write};
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+use crate::idn::to_unicode;
+use crate::write_handler::ResultsSubDomain;
+
+/// Output format of `baddns report`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Format {
+    Json,
+    // one name per line
+    Txt,
+    Csv,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Self {
+        match name {
+            "txt" => Format::Txt,
+            "csv" => Format::Csv,
+            _ => Format::Json,
+        }
+    }
+
+    pub fn from_filename(filename: &str) -> Self {
+        match filename.rsplit('.').next() {
+            Some(ext) => Self::parse(ext),
+            None => Format::Json,
+        }
+    }
+}
+
+/// Read a result file written by a scan. The `{}` closing every output is skipped,
+/// results written before unicode names were added get theirs.
+pub fn load_results(filename: &str) -> Result<Vec<ResultsSubDomain>, Error> {
+    let text = fs::read_to_string(filename).map_err(|e| Error::io(filename, e))?;
+    let values: Vec<serde_json::Value> = serde_json::from_str(text.as_str())
+        .map_err(|e| Error::io(filename, e.into()))?;
+
+    let mut results = Vec::new();
+    for v in values {
+        if v.as_object().map(|o| o.is_empty()).unwrap_or(false) {
+            continue
+        }
+        let mut r: ResultsSubDomain = serde_json::from_value(v).map_err(|e| Error::io(filename, e.into()))?;
+        if r.unicode.is_empty() {
+            r.unicode = to_unicode(r.subdomain.as_str());
+        }
+        results.push(r);
+    }
+    Ok(results)
+}
+
+/// Merge results by name, joining their records. The result is sorted by name.
+pub fn merge(results: Vec<ResultsSubDomain>) -> Vec<ResultsSubDomain> {
+    let mut merged: BTreeMap<String, ResultsSubDomain> = BTreeMap::new();
+    for r in results {
+        match merged.get_mut(&r.subdomain) {
+            Some(m) => {
+                m.collect.extend(r.collect);
+                m.excluded |= r.excluded;
+            }
+            None => {
+                merged.insert(r.subdomain.to_owned(), r);
+            }
+        }
+    }
+
+    merged.into_values().map(|mut r| {
+        r.collect.sort();
+        r.collect.dedup();
+        r
+    }).collect()
+}
+
+pub fn render<W: Write>(results: &[ResultsSubDomain], format: &Format, mut out: W) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, results)?;
+            out.write_all(b"\n")?;
+        }
+        Format::Txt => {
+            for r in results {
+                writeln!(out, "{}", r.subdomain)?;
+            }
+        }
+        Format::Csv => {
+            // names and records never contain commas, records are joined by `;`
+            writeln!(out, "subdomain,unicode,records,excluded")?;
+            for r in results {
+                writeln!(out, "{},{},{},{}", r.subdomain, r.unicode, r.collect.join(";"), r.excluded)?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn result(name: &str, collect: &[&str]) -> ResultsSubDomain {
+        ResultsSubDomain::new(name, collect.iter().map(|c| c.to_string()).collect(), false)
+    }
+
+    #[test]
+    fn test_merge_render() {
+        let results = merge(vec![
+            result("www.example.com.", &["10.0.0.2"]),
+            result("api.example.com.", &["10.0.0.1"]),
+            result("www.example.com.", &["10.0.0.3", "10.0.0.2"]),
+        ]);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].collect, vec!["10.0.0.2", "10.0.0.3"]);
+
+        let mut out = Vec::new();
+        render(&results, &Format::Csv, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "subdomain,unicode,records,excluded\napi.example.com,api.example.com,10.0.0.1,false\n\
+                    www.example.com,www.example.com,10.0.0.2;10.0.0.3,false\n");
+        assert_eq!(Format::from_filename("names.txt"), Format::Txt);
+    }
+
+    #[test]
+    fn test_load_legacy() {
+        let dir = crate::test_util::TempDir::new();
+        let file = dir.file("results.json", r#"[{"subdomain":"xn--0zwm56d.example.com","collect":["10.0.0.1"]},{}]"#);
+        let file = file.as_str();
+
+        let results = load_results(file).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].unicode, "测试.example.com");
+    }
+}
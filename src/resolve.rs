@@ -0,0 +1,56 @@
+use std::thread;
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, sync_channel};
+
+use pool_rs::pool::ThreadPool;
+
+use crate::query::{query_event, Protocol};
+use crate::signal::stop_flag;
+use crate::write_handler::ResultsSubDomain;
+
+/// Resolve `names` on `worker` threads with `resolvers`, the built-in public
+/// ones when it is empty. The names that answered arrive in the order they
+/// complete, no new query starts once the process is interrupted.
+pub fn resolve(names: Vec<String>, worker: usize, retry: usize, protocol: Protocol,
+               resolvers: Vec<SocketAddr>) -> impl Iterator<Item = ResultsSubDomain> {
+    let (result_send, result_recv) = channel();
+    let stop = stop_flag();
+    let resolvers = Arc::new(resolvers);
+
+    thread::spawn(move || {
+        let pool = ThreadPool::new(worker);
+
+        // one permit per worker, as in the query stage of a scan
+        let (permit_send, permit_recv) = sync_channel(worker);
+        // `permit_recv` is held here, filling it up to `worker` cannot fail
+        for _ in 0..worker {
+            let _ = permit_send.send(());
+        }
+
+        for name in names {
+            if stop.load(Ordering::SeqCst) {
+                break
+            }
+            // `permit_send` is held here too, waiting cannot fail
+            let _ = permit_recv.recv();
+            let permit = permit_send.clone();
+            let result_send = result_send.clone();
+            let protocol = protocol.clone();
+            let resolvers = resolvers.clone();
+
+            pool.execute(move || {
+                let mut collect = Vec::new();
+                query_event(name.as_str(), &mut collect, retry, protocol, &resolvers);
+                if !collect.is_empty() {
+                    let _ = result_send.send(ResultsSubDomain::new(name.as_str(), collect, false));
+                }
+                let _ = permit.send(());
+            });
+        }
+        debug!("[resolve] all names queued");
+    });
+
+    result_recv.into_iter()
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use crate::gen_handler::Item;
+
+/// Outcome of the sample taken below one parent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SampleResult {
+    pub domain: String,
+    pub sub: String,
+    pub depth: usize,
+    pub hits: usize,
+    pub size: usize,
+    pub passed: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Sample {
+    sub: String,
+    depth: usize,
+    // queries gen sent, known once it reported the sample complete
+    sent: Option<usize>,
+    checked: usize,
+    hits: usize,
+}
+
+/// Depth policy that queries the top `size` dictionary candidates below a new
+/// parent and only brute forces the full subtree when at least `threshold`
+/// of them resolve to non-wildcard records.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    size: usize,
+    threshold: usize,
+    samples: HashMap<String, Sample>,
+}
+
+impl Sampler {
+    pub fn new(size: usize, threshold: usize) -> Self {
+        Self {
+            size,
+            threshold,
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Register a sample below `item` drawn from a dictionary of `available`
+    /// candidates. Returns the sample size asked of gen, 0 when there is
+    /// nothing to sample. Gen may send fewer, see `generated`.
+    pub fn start(&mut self, item: &Item, available: usize) -> usize {
+        let size = self.size.min(available);
+        if size == 0 || self.samples.contains_key(&item.subdomain) {
+            return 0
+        }
+        self.samples.insert(item.subdomain.to_owned(), Sample {
+            sub: item.sub.to_owned(),
+            depth: item.depth,
+            sent: None,
+            checked: 0,
+            hits: 0,
+        });
+        size
+    }
+
+    /// Whether the sample below `domain` is still open.
+    pub fn contains(&self, domain: &str) -> bool {
+        self.samples.contains_key(domain)
+    }
+
+    /// Record the outcome of one sample query. Returns the result for its
+    /// parent once every query of the sample has come back.
+    pub fn record(&mut self, item: &Item, hit: bool) -> Option<SampleResult> {
+        let (_, parent) = item.subdomain.split_once('.')?;
+        let sample = self.samples.get_mut(parent)?;
+        sample.checked += 1;
+        if hit {
+            sample.hits += 1;
+        }
+        self.finish(parent)
+    }
+
+    /// Gen sent `sent` queries for the sample below `domain`, fewer than asked
+    /// when some were excluded. Returns the result if all of them are back.
+    pub fn generated(&mut self, domain: &str, sent: usize) -> Option<SampleResult> {
+        self.samples.get_mut(domain)?.sent = Some(sent);
+        self.finish(domain)
+    }
+
+    fn finish(&mut self, domain: &str) -> Option<SampleResult> {
+        let sample = self.samples.get(domain)?;
+        if sample.sent.map(|n| sample.checked < n).unwrap_or(true) {
+            return None
+        }
+
+        let sample = self.samples.remove(domain)?;
+        Some(SampleResult {
+            domain: domain.to_string(),
+            sub: sample.sub,
+            depth: sample.depth,
+            hits: sample.hits,
+            size: sample.checked,
+            passed: sample.hits >= self.threshold,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(sub: &str, subdomain: &str) -> Item {
+        Item {
+            sub: sub.to_string(),
+            subdomain: subdomain.to_string(),
+            depth: 1,
+            collect: None,
+            sample: true,
+            index: None,
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut s = Sampler::new(2, 1);
+        assert_eq!(s.start(&item("api", "api.example.com."), 100), 2);
+        assert_eq!(s.start(&item("api", "api.example.com."), 100), 0);
+        assert_eq!(s.record(&item("www", "www.api.example.com."), false), None);
+        assert_eq!(s.record(&item("dev", "dev.api.example.com."), true), None);
+
+        let r = s.generated("api.example.com.", 2).unwrap();
+        assert_eq!(r.domain, "api.example.com.");
+        assert_eq!(r.passed, true);
+        assert_eq!(r.size, 2);
+        assert_eq!(s.contains("api.example.com."), false);
+
+        // gen sent only one, the other was excluded
+        assert_eq!(s.start(&item("dev", "dev.example.com."), 100), 2);
+        assert_eq!(s.generated("dev.example.com.", 1), None);
+        let r = s.record(&item("www", "www.dev.example.com."), false).unwrap();
+        assert_eq!(r.passed, false);
+        assert_eq!(r.size, 1);
+
+        // nothing was sent at all
+        assert_eq!(s.start(&item("mail", "mail.example.com."), 100), 2);
+        assert_eq!(s.generated("mail.example.com.", 0).unwrap().size, 0);
+
+        assert_eq!(Sampler::new(50, 1).start(&item("www", "www.example.com."), 10), 10);
+    }
+}
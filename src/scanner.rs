@@ -0,0 +1,461 @@
+use std::thread;
+use std::vec;
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, sync_channel, Receiver};
+
+use indicatif::ProgressBar;
+
+use crate::dict::Dict;
+use crate::dict_set::{DictSet, DictWords};
+use crate::query::Protocol;
+use crate::target::{parse_target, scope_targets};
+use crate::psl::{SuffixList, SuffixPolicy};
+use crate::exclude::{Exclusions, ExcludeMode};
+use crate::wildcards::{Wildcards, wildcards_event};
+use crate::permutation::Permutator;
+use crate::learn::Learner;
+use crate::depth_grow::DepthGrower;
+use crate::sampling::Sampler;
+use crate::checkpoint::{Checkpoint, Checkpointer};
+use crate::signal::stop_flag;
+use crate::mem_util::state_management;
+use crate::handler::{subdomain_query_event, QueryOptions};
+use crate::check_handler::{check_event, CheckOptions};
+use crate::gen_handler::{gen_event, GenOptions};
+use crate::write_handler::ResultsSubDomain;
+use crate::error::Error;
+
+/// Configures a scan. Everything is passed in already loaded, the defaults
+/// are those of the command line.
+///
+/// ```no_run
+/// use baddns::dict::StreamDict;
+/// use baddns::dict_set::DictSet;
+/// use baddns::scanner::ScanBuilder;
+///
+/// # fn main() -> Result<(), baddns::error::Error> {
+/// let dicts = DictSet::new(StreamDict::new(&["domaindict-170W.txt".to_string()], false, false)?);
+/// let scan = ScanBuilder::new(vec!["example.com".to_string()], dicts)
+///     .worker(100)
+///     .build()
+///     .start();
+/// for r in scan {
+///     match r {
+///         Ok(r) => println!("{} {:?}", r.subdomain, r.collect),
+///         Err(e) => eprintln!("{}", e),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ScanBuilder {
+    targets: Vec<String>,
+    dicts: DictSet,
+    depth_dict: Dict,
+    depth: usize,
+    worker: usize,
+    retry: usize,
+    protocol: Protocol,
+    resolvers: Vec<SocketAddr>,
+    rate: usize,
+    queue_size: usize,
+    wildcards: Wildcards,
+    refresh: bool,
+    permutator: Option<Permutator>,
+    learn: bool,
+    learn_output: Option<String>,
+    grow_depth: Option<String>,
+    passive: Vec<String>,
+    sampler: Option<Sampler>,
+    exclude: Exclusions,
+    exclude_mode: ExcludeMode,
+    psl: SuffixList,
+    suffix_policy: SuffixPolicy,
+    registrable: bool,
+    checkpoint: Option<(String, Duration)>,
+    resume: Checkpoint,
+    progress: bool,
+}
+
+impl ScanBuilder {
+    /// `targets` are parsed like the lines of a target file, see `target::parse_target`.
+    pub fn new(targets: Vec<String>, dicts: DictSet) -> Self {
+        Self {
+            targets,
+            dicts,
+            depth_dict: Dict::from_vec(Vec::new()),
+            depth: 1,
+            worker: 500,
+            retry: 3,
+            protocol: Protocol::TCP,
+            resolvers: Vec::new(),
+            rate: 0,
+            queue_size: 10000,
+            wildcards: Wildcards::new(),
+            refresh: false,
+            permutator: None,
+            learn: false,
+            learn_output: None,
+            grow_depth: None,
+            passive: Vec::new(),
+            sampler: None,
+            exclude: Exclusions::new(),
+            exclude_mode: ExcludeMode::Drop,
+            psl: SuffixList::embedded(),
+            suffix_policy: SuffixPolicy::Refuse,
+            registrable: false,
+            checkpoint: None,
+            resume: Checkpoint::default(),
+            progress: false,
+        }
+    }
+
+    /// Labels worth querying below, see the `depth` dictionary.
+    pub fn depth_dict(mut self, depth_dict: Dict) -> Self {
+        self.depth_dict = depth_dict;
+        self
+    }
+
+    /// Levels below a target to query.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn worker(mut self, worker: usize) -> Self {
+        self.worker = worker;
+        self
+    }
+
+    pub fn retry(mut self, retry: usize) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Servers to query and to probe for wildcards instead of the built-in
+    /// public resolvers.
+    pub fn resolvers(mut self, resolvers: Vec<SocketAddr>) -> Self {
+        self.resolvers = resolvers;
+        self
+    }
+
+    /// Names queried per second at most, 0 does not limit. Retries and
+    /// wildcard probes are not counted.
+    pub fn rate(mut self, rate: usize) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Previously collected wildcard records, zones still cached are not
+    /// probed again unless `refresh` is set.
+    pub fn wildcards(mut self, wildcards: Wildcards, refresh: bool) -> Self {
+        self.wildcards = wildcards;
+        self.refresh = refresh;
+        self
+    }
+
+    pub fn permute(mut self, words: Vec<String>, cap: usize) -> Self {
+        self.permutator = Some(Permutator::new(words, cap));
+        self
+    }
+
+    /// Learn words from results, saved to `output` at the end if given.
+    pub fn learn(mut self, output: Option<String>) -> Self {
+        self.learn = true;
+        self.learn_output = output;
+        self
+    }
+
+    /// Grow the depth dictionary from results and save it to `output`.
+    /// `passive` names are known to exist and promote their labels up front.
+    pub fn grow_depth(mut self, output: String, passive: Vec<String>) -> Self {
+        self.grow_depth = Some(output);
+        self.passive = passive;
+        self
+    }
+
+    /// Sample a subtree before expanding it instead of asking the depth dictionary.
+    pub fn sample(mut self, size: usize, threshold: usize) -> Self {
+        self.sampler = Some(Sampler::new(size, threshold));
+        self
+    }
+
+    pub fn exclude(mut self, exclude: Exclusions, mode: ExcludeMode) -> Self {
+        self.exclude = exclude;
+        self.exclude_mode = mode;
+        self
+    }
+
+    pub fn public_suffix(mut self, psl: SuffixList, policy: SuffixPolicy, registrable: bool) -> Self {
+        self.psl = psl;
+        self.suffix_policy = policy;
+        self.registrable = registrable;
+        self
+    }
+
+    /// Save a checkpoint to `filename` every `interval`.
+    pub fn checkpoint(mut self, filename: &str, interval: Duration) -> Self {
+        self.checkpoint = Some((filename.to_owned(), interval));
+        self
+    }
+
+    /// Continue the scan saved in `resume`.
+    pub fn resume(mut self, resume: Checkpoint) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Draw a progress bar on the terminal.
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Scope the targets and probe them for wildcards.
+    pub fn build(self) -> Scanner {
+        let mut seen = HashSet::new();
+        let mut targets = Vec::new();
+        for line in self.targets.iter() {
+            match parse_target(line.as_str()) {
+                Ok(Some(t)) => {
+                    if seen.insert(t.to_owned()) {
+                        targets.push(t);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!("[scanner] {}: {:?}", e, line),
+            }
+        }
+        let mut targets = scope_targets(targets, &self.psl, &self.suffix_policy, self.registrable);
+        let exclude = &self.exclude;
+        targets.retain(|t| {
+            let keep = !exclude.is_excluded(t.as_str());
+            if !keep {
+                warn!("Target {} is out of scope", t);
+            }
+            keep
+        });
+
+        let mut wildcards = self.wildcards;
+        wildcards_event(targets.clone(), self.depth_dict.clone().get_dict(), self.worker, self.refresh,
+                        &self.resolvers, &mut wildcards);
+
+        Scanner {
+            options: ScanBuilder {
+                targets,
+                wildcards,
+                ..self
+            }
+        }
+    }
+}
+
+/// A scan ready to start, with its targets scoped and wildcards collected.
+pub struct Scanner {
+    options: ScanBuilder,
+}
+
+impl Scanner {
+    /// The targets left after parsing, the Public Suffix List and exclusions.
+    pub fn targets(&self) -> &Vec<String> {
+        &self.options.targets
+    }
+
+    pub fn wildcards(&self) -> &Wildcards {
+        &self.options.wildcards
+    }
+
+    /// Start the pipeline threads, results arrive through the returned `Scan`.
+    pub fn start(self) -> Scan {
+        let o = self.options;
+
+        let mut resume = o.resume;
+        // learned words are only appended to expansions while learning
+        if !o.learn {
+            resume.learned.clear();
+        }
+        let checkpointer = o.checkpoint.as_ref()
+            .map(|(filename, interval)| Checkpointer::new(filename.as_str(), *interval, &resume));
+
+        let stop = stop_flag();
+        let pb = if o.progress { ProgressBar::new(0) } else { ProgressBar::hidden() };
+
+        // check feeds expansions back to gen, so that edge stays unbounded to
+        // keep the bounded stages from waiting on each other in a cycle
+        let (gen_send, gen_recv) = channel();
+        let (result_send, result_recv) = sync_channel(o.queue_size);
+        let (check_send, check_recv) = sync_channel(o.queue_size);
+        let (query_send, query_recv) = sync_channel(o.queue_size);
+        let (statistical_send, statistical_recv) = channel();
+
+        let state = state_management(statistical_recv, gen_send.clone(), pb, o.targets.clone(), stop.clone());
+        let options = QueryOptions {
+            worker: o.worker,
+            retry: o.retry,
+            protocol: o.protocol,
+            resolvers: Arc::new(o.resolvers),
+            rate: o.rate,
+        };
+        let query = subdomain_query_event(query_recv, check_send, options, stop.clone(), statistical_send.clone());
+
+        // names brute forcing already queries are not permuted or learned again
+        let words = if o.permutator.is_some() || o.learn {
+            Arc::new(DictWords::new(&o.dicts))
+        } else {
+            Arc::new(DictWords::default())
+        };
+        let mut permutator = o.permutator;
+        if let Some(p) = permutator.as_mut() {
+            p.dictionary(words.clone());
+        }
+
+        let learner = if o.learn {
+            let mut l = Learner::new(&o.targets, o.learn_output);
+            l.dictionary(words);
+            Some(l)
+        } else {
+            None
+        };
+
+        let mut depth_dict = o.depth_dict;
+        let grower = match o.grow_depth {
+            Some(output) => {
+                let mut g = DepthGrower::new(&o.targets, output);
+                for name in o.passive.iter() {
+                    for (_, sub, _) in g.parents(name.as_str()) {
+                        g.promote(sub.as_str(), &mut depth_dict);
+                    }
+                }
+                Some(g)
+            }
+            None => None,
+        };
+
+        let options = CheckOptions {
+            depth: o.depth,
+            depth_dict,
+            dicts: o.dicts.clone(),
+            wildcards: o.wildcards,
+            permutator,
+            learner,
+            grower,
+            sampler: o.sampler,
+            exclude: o.exclude.clone(),
+            exclude_mode: o.exclude_mode,
+            psl: o.psl,
+            checkpointer,
+            resume: resume.clone(),
+            stop: stop.clone(),
+        };
+        let check = check_event(check_recv, gen_send, result_send, options, statistical_send.clone());
+        let options = GenOptions {
+            dicts: o.dicts,
+            targets: o.targets,
+            exclude: o.exclude,
+            stop: stop.clone(),
+        };
+        let gen = gen_event(gen_recv, query_send, options, &resume, statistical_send);
+
+        Scan {
+            resumed: resume.results.into_iter(),
+            results: result_recv,
+            stop,
+            // check is joined first, the other threads only finish after it
+            handles: vec![("check_handler", check), ("gen_handler", gen), ("handler", query), ("mem_util", state)],
+        }
+    }
+
+    /// Run the scan to the end, calling `f` with every result.
+    pub fn run<F: FnMut(ResultsSubDomain)>(self, mut f: F) -> Result<(), Error> {
+        for r in self.start() {
+            f(r?)
+        }
+        Ok(())
+    }
+}
+
+/// A running scan, iterating over its results. The results of a resumed
+/// scan come first, a failed scan ends with its error.
+///
+/// Dropping it before the end stops the scan and waits for it to drain.
+pub struct Scan {
+    resumed: vec::IntoIter<ResultsSubDomain>,
+    results: Receiver<ResultsSubDomain>,
+    // set by `stop` and by SIGINT/SIGTERM
+    stop: Arc<AtomicBool>,
+    handles: Vec<(&'static str, thread::JoinHandle<()>)>,
+}
+
+impl Scan {
+    /// Stop generating queries, the results of those in flight still arrive.
+    /// Other scans of the process keep running.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst)
+    }
+
+    // Threads after a panicked one are left behind, they may never finish.
+    fn join(&mut self) -> Result<(), Error> {
+        for (name, h) in self.handles.drain(..) {
+            if h.join().is_err() {
+                self.stop.store(true, Ordering::SeqCst);
+                return Err(Error::Panicked(name))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Scan {
+    type Item = Result<ResultsSubDomain, Error>;
+
+    fn next(&mut self) -> Option<Result<ResultsSubDomain, Error>> {
+        if let Some(r) = self.resumed.next() {
+            return Some(Ok(r))
+        }
+        match self.results.recv() {
+            Ok(r) => Some(Ok(r)),
+            Err(_) => self.join().err().map(Err),
+        }
+    }
+}
+
+impl Drop for Scan {
+    fn drop(&mut self) {
+        if self.handles.is_empty() {
+            return
+        }
+        self.stop();
+        for _ in self.results.iter() {}
+        let _ = self.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dict::StreamDict;
+
+    #[test]
+    fn test_empty_scan() {
+        let targets = vec!["10.0.0.1".to_string(), "com".to_string(), "# none".to_string()];
+        let scanner = ScanBuilder::new(targets, DictSet::new(StreamDict::new(&[], false, false).unwrap()))
+            .worker(1)
+            .build();
+        assert_eq!(scanner.targets().is_empty(), true);
+        assert_eq!(scanner.start().count(), 0);
+    }
+}
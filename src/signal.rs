@@ -0,0 +1,57 @@
+use std::process::exit;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit status of a scan stopped by SIGINT or SIGTERM.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// the flags of the scans still running, set by the first signal
+static FLAGS: Mutex<Vec<Weak<AtomicBool>>> = Mutex::new(Vec::new());
+
+/// Stop every scan on the first SIGINT/SIGTERM: no new queries are generated
+/// and the pipelines drain. A second signal exits at once.
+pub fn install() {
+    let handler = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            exit(EXIT_INTERRUPTED);
+        }
+        warn!("Stopping, waiting for in-flight queries. Press Ctrl-C again to quit now");
+        if let Ok(flags) = FLAGS.lock() {
+            flags.iter().filter_map(|f| f.upgrade()).for_each(|f| f.store(true, Ordering::SeqCst));
+        }
+    });
+    if let Err(e) = handler {
+        warn!("[signal] set handler msg: {:?}", e);
+    }
+}
+
+/// A new stop flag, set by SIGINT/SIGTERM once `install` was called.
+/// Setting it stops only the scan it was handed to.
+pub fn stop_flag() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(interrupted()));
+    if let Ok(mut flags) = FLAGS.lock() {
+        flags.retain(|f| f.strong_count() > 0);
+        flags.push(Arc::downgrade(&flag));
+    }
+    flag
+}
+
+/// Whether the process received SIGINT/SIGTERM.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stop_flag() {
+        let a = stop_flag();
+        let b = stop_flag();
+        a.store(true, Ordering::SeqCst);
+        assert_eq!(b.load(Ordering::SeqCst), false);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+use std::fs::File;
+use std::net::IpAddr;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader};
+
+use crate::idn::to_ascii;
+use crate::psl::{SuffixList, SuffixPolicy};
+use crate::query::gen_subdomain;
+
+/// Why a target line was rejected.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Rejected {
+    Ip,
+    Idn,
+    Invalid,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejected::Ip => write!(f, "IP address, not a domain"),
+            Rejected::Idn => write!(f, "invalid internationalised name"),
+            Rejected::Invalid => write!(f, "not a valid domain name"),
+        }
+    }
+}
+
+/// Extract the domain from a target line, which may be a bare name, a URL
+/// (`https://user@www.example.com:8443/path?q`) or a wildcard (`*.example.com`).
+/// Blank lines and `#` comments are `Ok(None)`. The name is returned in its
+/// ASCII form with a trailing dot.
+pub fn parse_target(line: &str) -> Result<Option<String>, Rejected> {
+    let line = match line.find('#') {
+        Some(n) => &line[..n],
+        None => line,
+    }.trim();
+    if line.is_empty() {
+        return Ok(None)
+    }
+
+    let host = match line.find("://") {
+        Some(n) => &line[n + 3..],
+        None => line,
+    };
+    let host = host.split(&['/', '?', '#'][..]).next().unwrap_or("");
+    let host = match host.rfind('@') {
+        Some(n) => &host[n + 1..],
+        None => host,
+    };
+
+    // bracketed or bare IPv6 literals carry colons of their own
+    let ip = host.trim_start_matches('[').split(']').next().unwrap_or("");
+    if ip.parse::<IpAddr>().is_ok() {
+        return Err(Rejected::Ip)
+    }
+    let host = match host.rfind(':') {
+        Some(n) if host[n + 1..].chars().all(|c| c.is_ascii_digit()) => &host[..n],
+        _ => host,
+    };
+    if host.parse::<IpAddr>().is_ok() {
+        return Err(Rejected::Ip)
+    }
+
+    let host = host.strip_prefix("*.").unwrap_or(host).trim_end_matches('.');
+    if host.is_empty() || host.contains('*') {
+        return Err(Rejected::Invalid)
+    }
+    let host = to_ascii(host).ok_or(Rejected::Idn)?;
+    if host.split('.').any(|l| l.is_empty()) {
+        return Err(Rejected::Invalid)
+    }
+    gen_subdomain(host.as_str(), ".").ok_or(Rejected::Invalid)?;
+
+    Ok(Some(host + "."))
+}
+
+/// Read targets from `filename`, or from stdin when it is `-`. Rejected lines
+/// are reported and skipped, duplicates keep their first position.
+pub fn load_targets(filename: &str) -> io::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = if filename == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(filename)?))
+    };
+
+    let mut seen = HashSet::new();
+    let mut targets = Vec::new();
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        match parse_target(line.as_str()) {
+            Ok(Some(t)) => {
+                if seen.insert(t.to_owned()) {
+                    targets.push(t);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("[target] {}:{} {}: {:?}", filename, n + 1, e, line),
+        }
+    }
+    Ok(targets)
+}
+
+/// Check targets against the Public Suffix List. Public suffixes are reported
+/// and, with `SuffixPolicy::Refuse`, dropped. With `registrable` every target
+/// is collapsed to its registrable domain.
+pub fn scope_targets(targets: Vec<String>, psl: &SuffixList, policy: &SuffixPolicy, registrable: bool) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut scoped = Vec::new();
+    for t in targets {
+        let t = match psl.registrable(t.as_str()) {
+            Some(r) if registrable => r + ".",
+            Some(_) => t,
+            None => {
+                match policy {
+                    SuffixPolicy::Refuse => {
+                        warn!("[target] {} is a public suffix, skipped", t);
+                        continue
+                    }
+                    SuffixPolicy::Warn => warn!("[target] {} is a public suffix", t),
+                }
+                t
+            }
+        };
+        if seen.insert(t.to_owned()) {
+            scoped.push(t);
+        }
+    }
+    scoped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        let ok = |s: &str| Ok(Some(s.to_string()));
+        assert_eq!(parse_target("Example.com"), ok("example.com."));
+        assert_eq!(parse_target("https://user@www.Example.com:8443/a/b?q=1"), ok("www.example.com."));
+        assert_eq!(parse_target("*.example.com. # scope"), ok("example.com."));
+        assert_eq!(parse_target("example.com:443"), ok("example.com."));
+        assert_eq!(parse_target("bücher.example"), ok("xn--bcher-kva.example."));
+        assert_eq!(parse_target("   "), Ok(None));
+        assert_eq!(parse_target("# comment"), Ok(None));
+        assert_eq!(parse_target("http://10.0.0.1:80/"), Err(Rejected::Ip));
+        assert_eq!(parse_target("[::1]:53"), Err(Rejected::Ip));
+        assert_eq!(parse_target("a..example.com"), Err(Rejected::Invalid));
+        assert_eq!(parse_target("*.*.example.com"), Err(Rejected::Invalid));
+    }
+
+    #[test]
+    fn test_scope_targets() {
+        let psl = SuffixList::parse("com\nco.uk\ngithub.io\n");
+        let targets: Vec<String> = vec!["co.uk.", "www.example.co.uk.", "api.example.co.uk.", "github.io."]
+            .into_iter().map(|t| t.to_string()).collect();
+
+        assert_eq!(scope_targets(targets.clone(), &psl, &SuffixPolicy::Refuse, true), vec!["example.co.uk."]);
+        assert_eq!(scope_targets(targets, &psl, &SuffixPolicy::Warn, false).len(), 4);
+    }
+}
@@ -0,0 +1,53 @@
+//! Fixtures shared by the unit tests.
+
+use std::fs;
+use std::process;
+use std::path::PathBuf;
+use std::env::temp_dir;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::dict_index::TEST_CACHE;
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory of its own for one test, removed with its files when dropped,
+/// also when an assert failed.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = temp_dir().join(format!("baddns-test-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::SeqCst)));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// The path of `name` in the directory.
+    pub fn path(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().to_string()
+    }
+
+    /// Write `text` to `name` in the directory and return its path.
+    pub fn file(&self, name: &str, text: &str) -> String {
+        let path = self.path(name);
+        fs::write(path.as_str(), text).unwrap();
+        path
+    }
+
+    /// Build the dictionary indexes of this thread in the directory instead
+    /// of the user's cache.
+    pub fn cache(&self) {
+        TEST_CACHE.with(|c| *c.borrow_mut() = Some(self.0.clone()));
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        TEST_CACHE.with(|c| {
+            let mut c = c.borrow_mut();
+            if c.as_ref() == Some(&self.0) {
+                *c = None;
+            }
+        });
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
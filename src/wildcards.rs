@@ -1,30 +1,203 @@
 use std::thread;
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::io::BufReader;
 use std::str::FromStr;
-use std::time::Duration;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::sync::mpsc::channel;
 
+use serde_json;
+use serde_derive::{Serialize, Deserialize};
+
 use pool_rs::pool::ThreadPool;
 use trust_dns_client::rr::{Name, DNSClass, RecordType};
 use trust_dns_client::tcp::TcpClientConnection;
 use trust_dns_client::client::{SyncClient, Client};
 
-use crate::query::{ALL_DNS_SERVER, query_response_handler};
+use trust_dns_client::op::DnsResponse;
+
+use crate::query::{default_resolvers, query_response_handler_record};
+
 
+/// Version of the wildcard cache format, caches without one hold bare values.
+pub const CACHE_VERSION: u32 = 1;
+
+/// A single answer returned for a random probe name.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(from = "CachedFingerprint")]
+pub struct Fingerprint {
+    pub depth: usize,
+    pub record_type: String,
+    pub value: String,
+}
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+// Version 0 caches stored the values only, their depth and type are unknown.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CachedFingerprint {
+    Value(String),
+    Fingerprint {
+        depth: usize,
+        record_type: String,
+        value: String,
+    },
+}
+
+impl From<CachedFingerprint> for Fingerprint {
+    fn from(f: CachedFingerprint) -> Self {
+        match f {
+            CachedFingerprint::Value(value) => Fingerprint {
+                depth: 0,
+                record_type: String::new(),
+                value,
+            },
+            CachedFingerprint::Fingerprint { depth, record_type, value } => Fingerprint {
+                depth,
+                record_type,
+                value,
+            },
+        }
+    }
+}
+
+/// Wildcard fingerprints collected for one zone, stamped with the time of the probe
+/// and the probe set it was taken with, see `probe_hash`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ZoneWildcards {
+    pub records: Vec<Fingerprint>,
+    pub updated: u64,
+    // 0 in caches written before it was kept, they never match
+    #[serde(default)]
+    pub probes: u64,
+}
+
+/// Per-zone summary written by the wildcard report mode.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ZoneReport {
+    pub zone: String,
+    pub wildcard: bool,
+    pub record_types: Vec<String>,
+    pub depths: Vec<usize>,
+    pub fingerprints: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct Wildcards {
-    pub lists: Vec<String>,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(skip)]
+    pub lists: HashSet<String>,
+    pub zones: BTreeMap<String, ZoneWildcards>,
 }
 
 impl Wildcards {
     pub fn new() -> Self {
         Self {
-            lists: Vec::new()
+            version: CACHE_VERSION,
+            lists: HashSet::new(),
+            zones: BTreeMap::new(),
+        }
+    }
+
+    /// Load the wildcard cache, dropping zones older than `ttl` seconds.
+    /// A missing or unreadable cache file yields an empty whitelist.
+    pub fn load(filename: &str, ttl: u64) -> Self {
+        let f = match File::open(filename) {
+            Ok(f) => f,
+            Err(_) => return Self::new(),
+        };
+
+        let mut w: Wildcards = match serde_json::from_reader(BufReader::new(f)) {
+            Ok(w) => w,
+            Err(e) => {
+                warn!("[wildcards] load cache {} msg: {:?}", filename, e);
+                return Self::new()
+            }
+        };
+
+        if w.version < CACHE_VERSION {
+            info!("Upgrade wildcard cache {} from version {}", filename, w.version);
+            w.version = CACHE_VERSION;
+        }
+        let now = timestamp();
+        w.zones.retain(|_, z| z.updated.saturating_add(ttl) > now);
+        w
+    }
+
+    pub fn save(&self, filename: &str) -> std::io::Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Add the cached fingerprints of `zone` to the whitelist. Returns false
+    /// when the zone is not in the cache or was probed with another probe
+    /// set than `probes`, and has to be probed.
+    pub fn use_cached(&mut self, zone: &str, probes: u64) -> bool {
+        let records = match self.zones.get(zone_key(zone).as_str()) {
+            Some(z) if z.probes == probes => z.records.clone(),
+            _ => return false,
+        };
+        for item in records {
+            self.set_item(item.value);
+        }
+        true
+    }
+
+    pub fn set_zone(&mut self, zone: &str, probes: u64, records: Vec<Fingerprint>) {
+        for item in records.iter() {
+            self.set_item(item.value.to_owned());
         }
+        self.zones.insert(zone_key(zone), ZoneWildcards {
+            records,
+            updated: timestamp(),
+            probes,
+        });
+    }
+
+    pub fn report(&self, zones: &[String]) -> Vec<ZoneReport> {
+        let mut reports = Vec::new();
+        for zone in zones {
+            let key = zone_key(zone);
+            let records = match self.zones.get(key.as_str()) {
+                Some(z) => z.records.clone(),
+                None => Vec::new(),
+            };
+
+            // records upgraded from a version 0 cache have no type or depth
+            let mut record_types: Vec<String> = records.iter()
+                .filter(|r| !r.record_type.is_empty())
+                .map(|r| r.record_type.to_owned()).collect();
+            let mut depths: Vec<usize> = records.iter().filter(|r| r.depth > 0).map(|r| r.depth).collect();
+            let mut fingerprints: Vec<String> = records.iter().map(|r| r.value.to_owned()).collect();
+            record_types.sort();
+            record_types.dedup();
+            depths.sort();
+            depths.dedup();
+            fingerprints.sort();
+            fingerprints.dedup();
+
+            reports.push(ZoneReport {
+                zone: key,
+                wildcard: !records.is_empty(),
+                record_types,
+                depths,
+                fingerprints,
+            });
+        }
+        reports
     }
 
     pub fn set_item(&mut self, item: String) {
-        self.lists.push(item)
+        self.lists.insert(item);
     }
 
     pub fn is_exist(&self, item: &String) -> bool {
@@ -32,9 +205,9 @@ impl Wildcards {
     }
 
     pub fn get_list(&mut self) -> Vec<String> {
-        self.lists.sort();
-        self.lists.dedup();
-        self.lists.clone()
+        let mut lists: Vec<String> = self.lists.iter().cloned().collect();
+        lists.sort();
+        lists
     }
 
     pub fn len(self) -> usize {
@@ -42,54 +215,112 @@ impl Wildcards {
     }
 }
 
-pub fn wildcards_event(domains: Vec<String>, depth: Vec<String>, worker: usize, w: &mut Wildcards) {
+/// Probe `domains` for wildcard records on every server of `resolvers`,
+/// the built-in public ones when it is empty.
+pub fn wildcards_event(domains: Vec<String>, depth: Vec<String>, worker: usize, refresh: bool,
+                       resolvers: &[SocketAddr], w: &mut Wildcards) {
+
+    let resolvers = Arc::new(if resolvers.is_empty() { default_resolvers() } else { resolvers.to_vec() });
+    let probe_set = probe_hash(&depth, &resolvers);
+
+    let mut probes: HashMap<String, Vec<Fingerprint>> = HashMap::new();
+    let mut cached = 0;
+    for domain in domains {
+        if !refresh && w.use_cached(domain.as_str(), probe_set) {
+            cached += 1;
+            continue
+        }
+        probes.insert(domain, Vec::new());
+    }
+    if cached > 0 {
+        info!("Reuse cached wildcard records for {} zones", cached);
+    }
+    let domains: Vec<String> = probes.keys().cloned().collect();
 
     let (check_send, check_recv) = channel();
     let pool = ThreadPool::new(worker);
 
-    let mut w_depth =vec!["d6p4lfaojz.".to_string()];
+    let mut w_depth =vec![("d6p4lfaojz.".to_string(), 1)];
     for d in depth {
         let sub = "d6p4lfaojz.".to_owned() + d.trim()+ ".";
-        w_depth.push(sub);
+        w_depth.push((sub, 2));
     }
 
 
     let init_wildcards = thread::spawn(move || {
         for domain in domains {
 
-            for d in w_depth.clone() {
+            for (d, level) in w_depth.clone() {
 
                 let subdomain = d.to_owned() + domain.trim();
                 let check_send = check_send.clone();
+                let domain = domain.to_owned();
+                let resolvers = resolvers.clone();
 
                 pool.execute(move || {
                     let mut collect = Vec::new();
-                    query_wildcards(subdomain.as_str(), &mut collect);
-                    check_send.send(collect).unwrap();
+                    query_wildcards(subdomain.as_str(), level, &resolvers, &mut collect);
+                    if check_send.send((domain, collect)).is_err() {
+                        warn!("[wildcards] send probe result");
+                    }
                 })
             }
         }
     });
 
-    for collect in check_recv {
-        for item in collect {
-            w.set_item(item);
+    for (domain, collect) in check_recv {
+        if let Some(records) = probes.get_mut(&domain) {
+            records.extend(collect);
         }
     }
-    init_wildcards.join().unwrap();
+    if init_wildcards.join().is_err() {
+        error!("[wildcards] probe thread panicked, the records may be incomplete");
+    }
+
+    for (domain, mut records) in probes {
+        records.sort();
+        records.dedup();
+        w.set_zone(domain.as_str(), probe_set, records);
+    }
 
     info!("{}", "Complete the whitelist generation operation");
 }
 
+/// Identifies the probes sent to a zone: the depth labels probed below it
+/// and the servers asked. Cached fingerprints only stand for the same probes.
+pub fn probe_hash(depth: &[String], resolvers: &[SocketAddr]) -> u64 {
+    let mut labels: Vec<&str> = depth.iter().map(|d| d.trim()).collect();
+    labels.sort_unstable();
+    labels.dedup();
+    let mut resolvers = resolvers.to_vec();
+    resolvers.sort_unstable();
+    resolvers.dedup();
+
+    let mut hasher = DefaultHasher::new();
+    labels.hash(&mut hasher);
+    resolvers.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn zone_key(zone: &str) -> String {
+    zone.trim().trim_end_matches('.').to_string()
+}
+
+fn timestamp() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    }
+}
 
-fn query_wildcards(subdomain: &str, collect: &mut Vec<String>) {
+fn query_wildcards(subdomain: &str, depth: usize, resolvers: &[SocketAddr], collect: &mut Vec<Fingerprint>) {
     let name = match Name::from_str(subdomain) {
         Ok(n) => {n},
         Err(_) => {return},
     };
 
-    for dns in ALL_DNS_SERVER.iter() {
-        let client = match TcpClientConnection::with_timeout(dns.parse().unwrap(), Duration::from_secs_f32(5.0)) {
+    for dns in resolvers {
+        let client = match TcpClientConnection::with_timeout(*dns, Duration::from_secs_f32(5.0)) {
             Ok(c) => SyncClient::new(c),
             Err(e) => {
                 warn!("[wildcards] tcp_connection. msg: {:?}", e);
@@ -99,20 +330,34 @@ fn query_wildcards(subdomain: &str, collect: &mut Vec<String>) {
 
         match client.query(&name, DNSClass::IN, RecordType::A) {
             Ok(q) => {
-                query_response_handler(q, collect)
+                fingerprint_handler(q, depth, collect)
             }
             Err(_) => {}
         }
 
         match client.query(&name, DNSClass::IN, RecordType::CNAME) {
             Ok(q) => {
-                query_response_handler(q, collect)
+                fingerprint_handler(q, depth, collect)
             }
             Err(_) => {}
         }
     }
 
 }
+
+fn fingerprint_handler(q: DnsResponse, depth: usize, collect: &mut Vec<Fingerprint>) {
+    for i in q.answers() {
+        let mut values = Vec::new();
+        query_response_handler_record(i, &mut values);
+        for value in values {
+            collect.push(Fingerprint {
+                depth,
+                record_type: i.rr_type().to_string(),
+                value,
+            });
+        }
+    }
+}
 #[cfg(test)]
 mod test {
     use super::*;
@@ -120,10 +365,48 @@ mod test {
     #[test]
     fn test_is_exist() {
         let mut w = Wildcards::new();
-        w.lists = vec!["170.33.0.251".to_string(), "31.13.83.16.t".to_string(),"www.58coin.com".to_string(), "www.58ex.com".to_string()];
+        w.lists = vec!["170.33.0.251".to_string(), "31.13.83.16.t".to_string(),"www.58coin.com".to_string(), "www.58ex.com".to_string()]
+            .into_iter().collect();
         assert_eq!(w.is_exist(&"1.1.1.1".to_string()), false);
         assert_eq!(w.is_exist(&"170.33.0.251".to_string()), true);
         assert_eq!(w.is_exist(&"www.58coin.com".to_string()), true);
     }
 
+    #[test]
+    fn test_use_cached() {
+        let resolvers = default_resolvers();
+        let probes = probe_hash(&["www".to_string()], &resolvers);
+        let mut w = Wildcards::new();
+        w.set_zone("example.com.", probes, vec![Fingerprint {
+            depth: 1,
+            record_type: "A".to_string(),
+            value: "1.2.3.4".to_string(),
+        }]);
+        let mut cached = Wildcards::new();
+        cached.zones = w.zones.clone();
+        // other depth labels or servers are probes of their own
+        assert_eq!(cached.use_cached("example.com.", probe_hash(&["www".to_string(), "api".to_string()], &resolvers)), false);
+        assert_eq!(cached.use_cached("example.com.", probe_hash(&["www".to_string()], &resolvers[..1])), false);
+        assert_eq!(cached.is_exist(&"1.2.3.4".to_string()), false);
+        assert_eq!(cached.use_cached("example.com.", probe_hash(&[" www".to_string()], &resolvers)), true);
+        assert_eq!(cached.use_cached("example.org.", probes), false);
+        assert_eq!(cached.is_exist(&"1.2.3.4".to_string()), true);
+
+        let report = cached.report(&["example.com.".to_string(), "example.org.".to_string()]);
+        assert_eq!(report[0].wildcard, true);
+        assert_eq!(report[0].record_types, vec!["A".to_string()]);
+        assert_eq!(report[1].wildcard, false);
+    }
+
+    #[test]
+    fn test_legacy_cache() {
+        let mut w: Wildcards = serde_json::from_str(r#"{"zones":{"example.com":{"records":["1.2.3.4"],"updated":1}}}"#).unwrap();
+        assert_eq!(w.version, 0);
+        // the probes are unknown, the zone is probed again
+        assert_eq!(w.use_cached("example.com", probe_hash(&[], &default_resolvers())), false);
+        let report = w.report(&["example.com".to_string()]);
+        assert_eq!(report[0].fingerprints, vec!["1.2.3.4".to_string()]);
+        assert_eq!(report[0].record_types.is_empty(), true);
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,183 @@
+use std::fmt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Outstanding work of a scan. Every unit is registered before the work
+/// that created it is released, so nothing is left exactly when the count
+/// drops to zero.
+///
+/// A unit is either a query, tracked through the stages it passes, or an
+/// expansion the gen thread has not finished generating yet.
+#[derive(Clone, Debug, Default)]
+pub struct Work {
+    targets: HashSet<String>,
+    queued: usize,
+    querying: usize,
+    checking: usize,
+    expansions: usize,
+    // outstanding units per target, "" for work outside every target
+    outstanding: HashMap<String, usize>,
+}
+
+/// What is left of a scan, see `Work::remaining`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Remaining {
+    pub queued: usize,
+    pub querying: usize,
+    pub checking: usize,
+    pub expansions: usize,
+    pub targets: BTreeMap<String, usize>,
+}
+
+impl fmt::Display for Remaining {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "queued {}, querying {}, checking {}, expansions {}",
+               self.queued, self.querying, self.checking, self.expansions)?;
+        for (target, n) in self.targets.iter() {
+            let target = if target.is_empty() { "-" } else { target.as_str() };
+            write!(f, "\n  {} {}", target, n)?;
+        }
+        Ok(())
+    }
+}
+
+impl Work {
+    pub fn new(targets: &[String]) -> Self {
+        Self {
+            targets: targets.iter().map(|t| t.trim_end_matches('.').to_string()).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// An expansion below `name` was handed to the gen thread.
+    pub fn expand(&mut self, name: &str) {
+        self.expansions += 1;
+        self.acquire(name);
+    }
+
+    /// The gen thread has generated every query of the expansion below `name`.
+    /// Returns the target it belonged to if that target has no work left.
+    pub fn expanded(&mut self, name: &str) -> Option<String> {
+        decrement(&mut self.expansions, "expansions");
+        self.release(name)
+    }
+
+    /// A query for `name` was generated.
+    pub fn queue(&mut self, name: &str) {
+        self.queued += 1;
+        self.acquire(name);
+    }
+
+    /// A worker started a queued query.
+    pub fn query(&mut self) {
+        decrement(&mut self.queued, "queued");
+        self.querying += 1;
+    }
+
+    /// A worker handed its answer to the check stage.
+    pub fn check(&mut self) {
+        decrement(&mut self.querying, "querying");
+        self.checking += 1;
+    }
+
+    /// The check stage finished with `name`, including any work it created.
+    /// Returns the target it belonged to if that target has no work left.
+    pub fn done(&mut self, name: &str) -> Option<String> {
+        decrement(&mut self.checking, "checking");
+        self.release(name)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queued + self.querying + self.checking + self.expansions == 0
+    }
+
+    pub fn remaining(&self) -> Remaining {
+        Remaining {
+            queued: self.queued,
+            querying: self.querying,
+            checking: self.checking,
+            expansions: self.expansions,
+            targets: self.outstanding.iter().map(|(t, n)| (t.to_owned(), *n)).collect(),
+        }
+    }
+
+    fn acquire(&mut self, name: &str) {
+        let target = self.target_of(name);
+        *self.outstanding.entry(target).or_insert(0) += 1;
+    }
+
+    fn release(&mut self, name: &str) -> Option<String> {
+        let target = self.target_of(name);
+        let n = match self.outstanding.get_mut(&target) {
+            Some(n) => n,
+            None => {
+                error!("[work] release {} without outstanding work", name);
+                return None
+            }
+        };
+        decrement(n, target.as_str());
+        if *n > 0 {
+            return None
+        }
+        self.outstanding.remove(&target);
+        Some(target).filter(|t| !t.is_empty())
+    }
+
+    // The longest target `name` is equal to or below.
+    fn target_of(&self, name: &str) -> String {
+        let mut name = name.trim_end_matches('.');
+        loop {
+            if self.targets.contains(name) {
+                return name.to_string()
+            }
+            match name.find('.') {
+                Some(n) => name = &name[n + 1..],
+                None => return String::new(),
+            }
+        }
+    }
+}
+
+// A unit released twice is a bug, it is logged rather than wrapping the
+// counter around and ending the scan early.
+fn decrement(n: &mut usize, what: &str) {
+    match n.checked_sub(1) {
+        Some(v) => *n = v,
+        None => error!("[work] {} released below zero", what),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_work() {
+        let mut w = Work::new(&["example.com.".to_string(), "example.org.".to_string()]);
+        w.expand("");
+        w.queue("example.com.");
+        w.queue("example.org.");
+        assert_eq!(w.expanded(""), None);
+
+        w.query();
+        w.check();
+        // the answer for example.com leads to an expansion below it
+        w.expand("example.com.");
+        assert_eq!(w.done("example.com."), None);
+        w.queue("www.example.com.");
+        assert_eq!(w.expanded("example.com."), None);
+        assert_eq!(w.remaining().targets.get("example.com"), Some(&1));
+
+        for name in vec!["www.example.com.", "example.org."] {
+            w.query();
+            w.check();
+            assert_eq!(w.is_idle(), false);
+            assert!(w.done(name).is_some());
+        }
+        assert_eq!(w.is_idle(), true);
+        assert_eq!(w.remaining(), Remaining::default());
+
+        // a stray release leaves the counts alone
+        assert_eq!(w.done("example.com."), None);
+        assert_eq!(w.is_idle(), true);
+    }
+}
@@ -1,57 +1,95 @@
-use std::thread;
 use std::io::Write;
-use std::process::exit;
 use std::fs::OpenOptions;
-use std::sync::mpsc::{Receiver, Sender};
+use std::io;
 
 use serde_json;
 use serde_derive::{Serialize, Deserialize};
 
-use crate::mem_util::Statue;
+use crate::error::Error;
+use crate::idn::to_unicode;
+use crate::wildcards::ZoneReport;
 
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct ResultsSubDomain {
     pub subdomain: String,
+    // `subdomain` with punycode labels decoded, missing from older outputs
+    #[serde(default)]
+    pub unicode: String,
     pub collect: Vec<String>,
+    // resolved into an excluded range, see `--exclude-mode flag`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub excluded: bool,
 }
 
-pub fn write_event(filename: String,
-                   results_recv: Receiver<ResultsSubDomain>,
-                   statistical_send: Sender<Statue>) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        debug!("[write_event]Start save result...");
-
-        let mut file = match OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(filename) {
-            Ok(f) => f,
-            Err(e) => {
-                error!("[write_event] {}", e);
-                exit(1);
-            }
-        };
+impl ResultsSubDomain {
+    pub fn new(domain: &str, collect: Vec<String>, excluded: bool) -> Self {
+        let subdomain = domain.trim_end_matches('.').to_owned();
+        Self {
+            unicode: to_unicode(subdomain.as_str()),
+            subdomain,
+            collect,
+            excluded
+        }
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Write results as a JSON array while they arrive, the file is valid JSON
+/// once `results` ends. Writing stops at the first error of the scan, which
+/// is returned after the array is closed.
+pub fn write_event<I: IntoIterator<Item = Result<ResultsSubDomain, Error>>>(filename: &str, results: I) -> Result<(), Error> {
+    debug!("[write_event]Start save result...");
 
-        file.write_all(b"[\n").unwrap();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(filename)
+        .map_err(|e| Error::io(filename, e))?;
 
-        debug!("[write_event] recv...");
+    file.write_all(b"[\n").map_err(|e| Error::io(filename, e))?;
 
-        for q in results_recv {
-            match statistical_send.send(Statue::Write) {
-                Ok(_) => {},
-                Err(_) => {
-                    error!("[write_handler] send Statue::Write");
-                }
-            };
-            serde_json::to_writer(&mut file, &q).unwrap();
-            drop(q);
-            file.write_all(b",\n").unwrap();
+    debug!("[write_event] recv...");
+
+    let mut failed = None;
+    for q in results {
+        let q = match q {
+            Ok(q) => q,
+            Err(e) => {
+                failed = Some(e);
+                break
+            }
         };
+        // the array is still closed when a result cannot be written
+        let written = serde_json::to_writer(&mut file, &q).map_err(io::Error::from)
+            .and_then(|_| file.write_all(b",\n"));
+        if let Err(e) = written {
+            failed = Some(Error::io(filename, e));
+            break
+        }
+    };
 
-        file.write_all(b"{}\n]").unwrap();
+    file.write_all(b"{}\n]").map_err(|e| Error::io(filename, e))?;
+    file.sync_all().map_err(|e| Error::io(filename, e))?;
 
-        debug!("[write_event] save results end");
-    })
-}
\ No newline at end of file
+    debug!("[write_event] save results end");
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+pub fn write_report(filename: &str, reports: &[ZoneReport]) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(filename)?;
+    serde_json::to_writer_pretty(file, reports)?;
+    Ok(())
+}